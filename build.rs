@@ -0,0 +1,34 @@
+use std::{env, fs, path::Path};
+
+// Names the bundled compiler after the zen revision Cargo.lock pins, e.g. "v0.3.0+2fcaa0f",
+// so the version reported by the API always matches the interpreter that was built in
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    let lock = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.lock");
+    let version = fs::read_to_string(lock)
+        .ok()
+        .and_then(|lock| zen_version(&lock))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=ZEN_COMPILER_VERSION={}", version);
+}
+
+fn zen_version(lock: &str) -> Option<String> {
+    let package = lock
+        .split("[[package]]")
+        .find(|package| field(package, "name") == Some("zen"))?;
+    let version = field(package, "version")?;
+    // Git sources end in "#<commit>"; registry ones carry no revision
+    match field(package, "source").and_then(|source| source.split_once('#')) {
+        Some((_, commit)) => Some(format!("v{}+{}", version, &commit[..commit.len().min(7)])),
+        None => Some(format!("v{}", version)),
+    }
+}
+
+fn field<'a>(package: &'a str, key: &str) -> Option<&'a str> {
+    package.lines().find_map(|line| {
+        let (name, value) = line.split_once(" = ")?;
+        (name == key).then(|| value.trim_matches('"'))
+    })
+}
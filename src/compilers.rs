@@ -0,0 +1,91 @@
+use std::{borrow::Cow, path::PathBuf};
+
+use shuttle_runtime::SecretStore;
+
+use crate::COMPILER_VERSION;
+
+/// One version of the zen interpreter that programs can run on.
+#[derive(Debug, Clone)]
+pub struct Compiler {
+    pub version: Cow<'static, str>,
+    // `None` runs the interpreter built into this server; otherwise the runner binary to spawn
+    pub(crate) runner: Option<PathBuf>,
+}
+
+impl Compiler {
+    /// The zen this server was built with.
+    pub const BUNDLED: Compiler = Compiler {
+        version: Cow::Borrowed(COMPILER_VERSION),
+        runner: None,
+    };
+}
+
+/// A request named a version that is not configured.
+#[derive(Debug)]
+pub struct UnknownVersion {
+    pub version: String,
+    pub versions: Vec<String>,
+}
+
+/// The compiler versions a request can pick from.
+///
+/// Besides the bundled one, `RUN_COMPILERS` lists other versions as `version=path` pairs
+/// separated by commas. Each path is a build of this server against that zen version; it is only
+/// ever started in runner mode, so it speaks the same request and reply as the bundled runner.
+#[derive(Debug)]
+pub struct Compilers {
+    // The bundled compiler first, then the others in the order they were configured
+    compilers: Vec<Compiler>,
+}
+
+impl Compilers {
+    pub fn init(secrets: &SecretStore) -> Compilers {
+        let mut compilers = vec![Compiler::BUNDLED];
+        let configured = secrets.get("RUN_COMPILERS").unwrap_or_default();
+        for entry in configured
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+        {
+            let (version, path) = entry
+                .split_once('=')
+                .unwrap_or_else(|| panic!("RUN_COMPILERS entries must look like version=path"));
+            let (version, path) = (version.trim(), PathBuf::from(path.trim()));
+            if compilers.iter().any(|compiler| compiler.version == version) {
+                panic!("RUN_COMPILERS lists {} more than once", version);
+            }
+            if !path.is_file() {
+                panic!(
+                    "RUN_COMPILERS: no runner for {} at {}",
+                    version,
+                    path.display()
+                );
+            }
+            compilers.push(Compiler {
+                version: Cow::Owned(version.to_string()),
+                runner: Some(path),
+            });
+        }
+        Compilers { compilers }
+    }
+
+    /// The compiler for `version`, or the bundled one when no version is asked for.
+    pub fn get(&self, version: Option<&str>) -> Result<&Compiler, UnknownVersion> {
+        let Some(version) = version else {
+            return Ok(&self.compilers[0]);
+        };
+        self.compilers
+            .iter()
+            .find(|compiler| compiler.version == version)
+            .ok_or_else(|| UnknownVersion {
+                version: version.to_string(),
+                versions: self.versions(),
+            })
+    }
+
+    pub fn versions(&self) -> Vec<String> {
+        self.compilers
+            .iter()
+            .map(|compiler| compiler.version.to_string())
+            .collect()
+    }
+}
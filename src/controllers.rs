@@ -1,3 +1,14 @@
 pub mod auth;
 pub mod authentication;
+pub mod comparator;
 pub mod compile_code;
+pub mod diagnostic;
+pub mod output_diff;
+pub mod pagination;
+pub mod problems;
+pub mod run_session;
+pub mod run_ws;
+pub mod scoring;
+pub mod snippets;
+pub mod submissions;
+pub mod workspaces;
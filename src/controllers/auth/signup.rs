@@ -2,8 +2,14 @@ use crate::controllers::authentication::{MyState, User, UserData};
 use axum::{extract, Json};
 use bcrypt::{hash, DEFAULT_COST};
 use http::StatusCode;
+use lazy_static::lazy_static;
 use serde::Serialize;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+
+lazy_static! {
+    // Two signups for the same username must not both pass the uniqueness check
+    static ref SIGNUP_LOCK: Mutex<()> = Mutex::new(());
+}
 
 #[derive(Debug, Serialize)]
 pub struct SignupResponse {
@@ -40,38 +46,59 @@ pub async fn signup(
             token: None,
         }));
     }
-    let data_result = state.persist.load::<UserData>("data");
-    let mut data = match data_result {
-        Ok(data) => data,
-        Err(e) => {
+    let hashed_password = hash(&req.password, DEFAULT_COST).unwrap();
+    let saved = {
+        let _guard = SIGNUP_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let data_result = state.persist.load::<UserData>("data");
+        let mut data = match data_result {
+            Ok(data) => data,
+            Err(e) => {
+                return Ok(Json(SignupResponse {
+                    status_code: StatusCode::INTERNAL_SERVER_ERROR.into(),
+                    message: e.to_string(),
+                    token: None,
+                }))
+            }
+        };
+        // Check if a user with the same email already exists
+        if data.people.iter().any(|person| person.email == req.email) {
             return Ok(Json(SignupResponse {
-                status_code: StatusCode::INTERNAL_SERVER_ERROR.into(),
-                message: e.to_string(),
+                status_code: StatusCode::BAD_REQUEST.into(),
+                message: "A user with this email already exists".to_string(),
                 token: None,
-            }))
+            }));
         }
+        // Tokens only carry the username, so it has to identify exactly one account
+        if data
+            .people
+            .iter()
+            .any(|person| person.username.eq_ignore_ascii_case(req.username.trim()))
+        {
+            return Ok(Json(SignupResponse {
+                status_code: StatusCode::BAD_REQUEST.into(),
+                message: "This username is already taken".to_string(),
+                token: None,
+            }));
+        }
+        data.people.push(User {
+            name: req.name,
+            username: req.username.trim().to_string(),
+            password: hashed_password,
+            email: req.email,
+            verification_code: None,
+        });
+        data.total_records += 1;
+
+        state.persist.save::<UserData>("data", data)
     };
-    // Check if a user with the same email already exists
-    if data.people.iter().any(|person| person.email == req.email) {
-        return Ok(Json(SignupResponse {
-            status_code: StatusCode::BAD_REQUEST.into(),
-            message: "A user with this email already exists".to_string(),
-            token: None,
-        }));
-    }
-    let hashed_password = hash(&req.password, DEFAULT_COST).unwrap();
-    data.people.push(User {
-        name: req.name,
-        username: req.username.clone(),
-        password: hashed_password,
-        email: req.email,
-        verification_code: None,
-    });
-    data.total_records += 1;
 
-    match state.persist.save::<UserData>("data", data) {
+    match saved {
         Ok(_) => {
-            let token = crate::controllers::authentication::create_jwt(req.username.clone()).await;
+            let token =
+                crate::controllers::authentication::create_jwt(req.username.trim().to_string())
+                    .await;
             // Return a JSON response with status code and message
             let response = SignupResponse {
                 status_code: StatusCode::CREATED.into(),
@@ -1,15 +1,28 @@
 use std::{
-    fmt::Display,
+    fmt::{Display, Write},
+    io,
     sync::Arc,
     time::{Duration, SystemTime},
 };
 
-use axum::{routing::post, Router};
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequestParts},
+    http::request::Parts,
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use http::StatusCode;
 
 use jsonwebtoken::{decode, DecodingKey, EncodingKey, Validation};
 use lazy_static::lazy_static;
-use serde::{Deserialize, Serialize};
-use shuttle_persist::PersistInstance;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use shuttle_persist::{PersistError, PersistInstance};
 use shuttle_runtime::SecretStore;
 use std::sync::Mutex;
 use tower_http::add_extension::AddExtensionLayer;
@@ -95,12 +108,153 @@ pub(crate) fn validate_jwt(token: &str, secret: String) -> jsonwebtoken::errors:
     .map(|data| data.claims)
 }
 
-pub fn auth_routes(persist: PersistInstance, shuttle_secrets: SecretStore) -> Router {
-    let state = Arc::new(MyState {
-        persist: Arc::new(persist),
-        secrets: Arc::new(shuttle_secrets),
-    });
+impl MyState {
+    pub fn new(persist: PersistInstance, shuttle_secrets: SecretStore) -> Self {
+        Self {
+            persist: Arc::new(persist),
+            secrets: Arc::new(shuttle_secrets),
+        }
+    }
+
+    /// Runs `f` against the persist store on the blocking thread pool, since every persist call
+    /// is file I/O.
+    pub(crate) async fn blocking<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&PersistInstance) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let persist = self.persist.clone();
+        tokio::task::spawn_blocking(move || f(&persist))
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+}
+
+// Admins are the users listed in the comma separated ADMIN_USERNAMES secret
+pub(crate) fn is_admin(secrets: &SecretStore, claims: &Claims) -> bool {
+    secrets
+        .get("ADMIN_USERNAMES")
+        .is_some_and(|admins| admins.split(',').any(|admin| admin.trim() == claims.sub))
+}
+
+/// Loads `key`, treating a key that was never saved as `T::default()`. Any other failure is an
+/// error, so a read that went wrong is never mistaken for empty data and saved over.
+pub(crate) fn load_or_default<T: DeserializeOwned + Default>(
+    persist: &PersistInstance,
+    key: &str,
+) -> Result<T, PersistError> {
+    match persist.load::<T>(key) {
+        Err(PersistError::Open(e)) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        loaded => loaded,
+    }
+}
+
+/// Seconds since the Unix epoch, as stored in timestamps.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// A persist key for data that belongs to `username`. Usernames are free text, so they are hex
+/// encoded to make a safe key.
+pub(crate) fn user_key(prefix: &str, username: &str) -> String {
+    username.bytes().fold(prefix.to_string(), |mut key, byte| {
+        let _ = write!(key, "{:02x}", byte);
+        key
+    })
+}
+
+/// The caller of a route that needs a login. Requests without a valid Bearer token get `401`.
+pub struct LoggedIn(pub(crate) Claims);
+
+/// The caller of a route that anonymous users may use as well. A missing, invalid or expired
+/// token all count as anonymous, so a stale token left in the browser never stops a run.
+pub struct MaybeLoggedIn(pub(crate) Option<Claims>);
+
+/// A caller listed in `ADMIN_USERNAMES`. Other users get `403`.
+pub struct Admin;
+
+#[derive(Debug, Serialize)]
+pub struct UnauthorizedResponse {
+    status_code: u16,
+    message: String,
+}
+
+impl MaybeLoggedIn {
+    pub(crate) fn username(self) -> Option<String> {
+        self.0.map(|claims| claims.sub)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for LoggedIn {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match caller(parts, state).await? {
+            Some(claims) => Ok(LoggedIn(claims)),
+            None => Err(unauthorized("Invalid token.")),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for MaybeLoggedIn {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        caller(parts, state).await.map(MaybeLoggedIn)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Admin {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let LoggedIn(claims) = LoggedIn::from_request_parts(parts, state).await?;
+        let Extension(app) = Extension::<Arc<MyState>>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        if is_admin(&app.secrets, &claims) {
+            Ok(Admin)
+        } else {
+            Err(respond(StatusCode::FORBIDDEN, "Only admins can do this."))
+        }
+    }
+}
+
+// The claims of the request's Bearer token, `None` when there is none or it does not validate
+async fn caller<S: Send + Sync>(parts: &mut Parts, state: &S) -> Result<Option<Claims>, Response> {
+    let Extension(app) = Extension::<Arc<MyState>>::from_request_parts(parts, state)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let header = TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state).await;
+    let Ok(TypedHeader(auth_header)) = header else {
+        return Ok(None);
+    };
+    let secret = app
+        .secrets
+        .get("SECRET_KEY")
+        .unwrap_or_else(|| panic!("Expected SECRET_KEY in secrets!"));
+    Ok(validate_jwt(auth_header.token(), secret).ok())
+}
+
+pub(crate) fn unauthorized(message: &str) -> Response {
+    respond(StatusCode::UNAUTHORIZED, message)
+}
+
+fn respond(status: StatusCode, message: &str) -> Response {
+    let body = UnauthorizedResponse {
+        status_code: status.into(),
+        message: message.to_string(),
+    };
+    (status, Json(body)).into_response()
+}
 
+pub fn auth_routes(state: Arc<MyState>) -> Router {
     let secret_key = jsonwebtoken::EncodingKey::from_secret(
         state
             .secrets
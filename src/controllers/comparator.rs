@@ -0,0 +1,138 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_TOLERANCE: f64 = 1e-6;
+
+/// How a testcase decides whether the program output matches `expected_output`.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Comparator {
+    /// Identical output, ignoring only trailing whitespace at the very end.
+    #[default]
+    Exact,
+    /// Same lines after trimming them, collapsing runs of whitespace and dropping `\r`
+    /// and trailing blank lines.
+    Whitespace,
+    /// The same lines (normalized as for `Whitespace`) in any order.
+    LineSet,
+    /// Same tokens, where numbers may differ by at most `tolerance`.
+    Numeric {
+        #[serde(default = "default_tolerance")]
+        tolerance: f64,
+    },
+    /// `expected_output` is a regular expression that must match the whole output.
+    Regex,
+}
+
+fn default_tolerance() -> f64 {
+    DEFAULT_TOLERANCE
+}
+
+impl Comparator {
+    /// Fails only when the testcase itself is unusable, e.g. an invalid regular expression.
+    pub fn matches(&self, actual: &str, expected: &str) -> Result<bool, String> {
+        match self {
+            Comparator::Exact => Ok(actual.trim_end() == expected),
+            Comparator::Whitespace => Ok(normalized_lines(actual) == normalized_lines(expected)),
+            Comparator::LineSet => {
+                let mut actual = normalized_lines(actual);
+                let mut expected = normalized_lines(expected);
+                actual.sort_unstable();
+                expected.sort_unstable();
+                Ok(actual == expected)
+            }
+            Comparator::Numeric { tolerance } => Ok(numbers_match(actual, expected, *tolerance)),
+            Comparator::Regex => Ok(pattern(expected)?.is_match(actual.trim_end())),
+        }
+    }
+
+    /// Checks up front that `expected` can be used with this comparator, so a stored testcase
+    /// never fails at grading time.
+    pub fn check(&self, expected: &str) -> Result<(), String> {
+        match self {
+            Comparator::Regex => pattern(expected).map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn pattern(expected: &str) -> Result<Regex, String> {
+    Regex::new(&format!("^(?:{})$", expected))
+        .map_err(|e| format!("Invalid expected_output pattern: {}", e))
+}
+
+fn normalized_lines(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = text
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect();
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+fn numbers_match(actual: &str, expected: &str, tolerance: f64) -> bool {
+    let actual: Vec<&str> = actual.split_whitespace().collect();
+    let expected: Vec<&str> = expected.split_whitespace().collect();
+    actual.len() == expected.len()
+        && actual.iter().zip(&expected).all(|(actual, expected)| {
+            match (actual.parse::<f64>(), expected.parse::<f64>()) {
+                (Ok(actual), Ok(expected)) => (actual - expected).abs() <= tolerance,
+                _ => actual == expected,
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Comparator;
+
+    fn matches(comparator: Comparator, actual: &str, expected: &str) -> bool {
+        comparator.matches(actual, expected).unwrap()
+    }
+
+    #[test]
+    fn exact_ignores_only_trailing_whitespace() {
+        assert!(matches(Comparator::Exact, "1 2\n3\n\n", "1 2\n3"));
+        assert!(!matches(Comparator::Exact, "1  2\n3", "1 2\n3"));
+        assert!(!matches(Comparator::Exact, " 1 2\n3", "1 2\n3"));
+    }
+
+    #[test]
+    fn whitespace_normalizes_spacing_and_line_endings() {
+        assert!(matches(
+            Comparator::Whitespace,
+            "  1   2 \r\n3\t\n\n\n",
+            "1 2\n3"
+        ));
+        assert!(!matches(Comparator::Whitespace, "1 2 3", "1 2\n3"));
+    }
+
+    #[test]
+    fn line_set_ignores_order_but_not_duplicates() {
+        assert!(matches(Comparator::LineSet, "b\na\nc\n", "a\nb\nc"));
+        assert!(!matches(Comparator::LineSet, "a\na\nb", "a\nb\nb"));
+    }
+
+    #[test]
+    fn numeric_allows_the_tolerance_and_compares_words_exactly() {
+        let numeric = Comparator::Numeric { tolerance: 0.01 };
+        assert!(matches(numeric.clone(), "3.141 2", "3.14 2.0"));
+        assert!(!matches(numeric.clone(), "3.2", "3.14"));
+        assert!(matches(numeric.clone(), "area 3.141", "area 3.14"));
+        assert!(!matches(numeric.clone(), "Area 3.14", "area 3.14"));
+        assert!(!matches(numeric, "3.14 2", "3.14"));
+    }
+
+    #[test]
+    fn regex_must_match_the_whole_output() {
+        assert!(matches(Comparator::Regex, "took 12ms\n", r"took \d+ms"));
+        assert!(!matches(Comparator::Regex, "it took 12ms", r"took \d+ms"));
+        assert!(!matches(Comparator::Regex, "1|2", "1|2"));
+        assert!(Comparator::Regex.matches("x", "(").is_err());
+        assert!(Comparator::Regex.check("(").is_err());
+        assert!(Comparator::Regex.check(r"\d+").is_ok());
+        assert!(Comparator::Exact.check("(").is_ok());
+    }
+}
@@ -1,68 +1,653 @@
-use axum::{extract, Json};
-use serde::{Deserialize, Serialize};
-use zen::run_program;
+use std::sync::Arc;
+
+use axum::{
+    extract,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures_util::stream::{self, StreamExt};
+use http::{header, StatusCode};
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::{
+    compilers::{Compiler, Compilers, UnknownVersion},
+    controllers::{
+        authentication::{unauthorized, MaybeLoggedIn, MyState},
+        comparator::Comparator,
+        diagnostic::Diagnostic,
+        output_diff::{first_difference, Difference},
+        scoring::{score, QuizVerdict, Score},
+        submissions::{record, Verdict},
+    },
+    run_cache::{CacheStats, RunCache},
+    run_config::RunConfig,
+    run_queue::{QueueFull, QueueInfo, QueueStatus, RunQueue, Ticket},
+    sandbox::{self, Metrics, RunResult},
+};
 
 #[derive(Deserialize)]
 pub struct CodeCompileRequest {
     pub code: String,
     pub input: String,
+    /// Always run the program, even when an identical run is cached.
+    #[serde(default)]
+    pub no_cache: bool,
+    /// Runs the interpreter in debug mode and returns its `trace`. Only for logged-in users of
+    /// `/api/compile` and `/api/compile/batch`.
+    #[serde(default)]
+    pub debug: bool,
+    /// The compiler version to run on, one of `/api/versions`; the bundled one by default.
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Success,
+    Error,
+    RuntimeError,
+    TimeLimitExceeded,
 }
+
 #[derive(Serialize)]
 pub struct CodeOutputResponse {
-    pub output: Result<String, String>,
+    pub status: RunStatus,
+    pub output: Result<String, Diagnostic>,
+    /// `output` with the error flattened into the old "[ERROR]" string, for older clients.
+    pub legacy_output: Result<String, String>,
+    pub truncated: bool,
+    pub dropped_bytes: usize,
+    pub metrics: Metrics,
+    pub queue: QueueInfo,
+    /// The result of an earlier identical run; `metrics` are the ones it had.
+    pub cached: bool,
+    /// What the interpreter printed in debug mode, kept apart from `output`.
+    pub trace: Option<String>,
+    /// The compiler version the program ran on.
+    pub version: String,
+}
+
+#[derive(Deserialize)]
+pub struct CodeBatchRequest {
+    pub runs: Vec<CodeCompileRequest>,
+}
+
+#[derive(Serialize)]
+pub struct BatchResponse {
+    /// In the order of `runs`.
+    pub results: Vec<CodeOutputResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnknownVersionResponse {
+    status_code: u16,
+    message: String,
+    versions: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct VersionsResponse {
+    /// Used when a request does not name a version.
+    pub default: String,
+    pub versions: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchTooLargeResponse {
+    status_code: u16,
+    message: String,
+    max_batch_size: usize,
 }
 
 #[derive(Deserialize)]
 pub struct CodeQuizRequest {
     pub code: String,
     pub testcases: Vec<Testcase>,
+    /// Used for every testcase that does not pick its own.
+    #[serde(default)]
+    pub comparator: Comparator,
+    #[serde(default)]
+    pub mode: QuizMode,
 }
 
-#[derive(Deserialize)]
+/// How a quiz walks through its testcases. Results are always in testcase order.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum QuizMode {
+    /// One testcase after the other.
+    #[default]
+    Sequential,
+    /// Sequential, but stops at the first testcase that does not pass.
+    StopAtFirstFailure,
+    /// Also runs testcases on workers that are idle when the quiz starts.
+    Parallel,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Testcase {
     pub input: String,
     pub expected_output: String,
+    pub comparator: Option<Comparator>,
+    /// Visible testcases report the outputs and where they differ; hidden ones only pass/fail.
+    #[serde(default)]
+    pub visible: bool,
+    /// Points earned when the testcase passes.
+    #[serde(default = "default_weight", deserialize_with = "deserialize_weight")]
+    pub weight: f64,
+    #[serde(default = "default_group")]
+    pub group: String,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn default_group() -> String {
+    "default".to_string()
+}
+
+fn deserialize_weight<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let weight = f64::deserialize(deserializer)?;
+    if weight.is_finite() && weight >= 0.0 {
+        Ok(weight)
+    } else {
+        Err(de::Error::custom("weight must be a non-negative number"))
+    }
+}
+
+#[derive(Serialize)]
+pub struct TestcaseResult {
+    pub status: RunStatus,
+    pub verdict: QuizVerdict,
+    pub passed: Result<bool, Diagnostic>,
+    pub weight: f64,
+    pub group: String,
+    pub comparator: Comparator,
+    pub truncated: bool,
+    pub dropped_bytes: usize,
+    pub metrics: Metrics,
+    pub details: Option<TestcaseDetails>,
+}
+
+#[derive(Serialize)]
+pub struct TestcaseDetails {
+    pub expected_output: String,
+    /// `None` when the program did not produce output (it failed or timed out).
+    pub actual_output: Option<String>,
+    pub difference: Option<Difference>,
 }
 
 #[derive(Serialize)]
 pub struct QuizResponse {
     pub output_match: Vec<Result<bool, String>>,
+    pub results: Vec<TestcaseResult>,
+    #[serde(flatten)]
+    pub score: Score,
+    /// Testcases left out of `results` by `stop_at_first_failure`; they do not count towards
+    /// `max_score`.
+    pub skipped: usize,
+    pub queue: QueueInfo,
+}
+
+/// The outcome of one program run, before it is shaped into a response.
+#[derive(Clone)]
+pub(crate) struct Execution {
+    pub(crate) status: RunStatus,
+    pub(crate) output: Result<String, Diagnostic>,
+    pub(crate) dropped_bytes: usize,
+    pub(crate) metrics: Metrics,
+    pub(crate) trace: Option<String>,
 }
 
+const HIDDEN_COMPARATOR_ERROR: &str = "This testcase cannot be graded.";
+
+const DEBUG_NEEDS_LOGIN: &str = "Log in to run programs in debug mode.";
+
+// Answers from the cache never wait in line
+const CACHED: QueueInfo = QueueInfo {
+    position: 0,
+    waited_ms: 0,
+};
+
+#[derive(Debug, Serialize)]
+pub struct ServerBusyResponse {
+    status_code: u16,
+    message: String,
+    retry_after: u64,
+}
+
+impl IntoResponse for QueueFull {
+    fn into_response(self) -> Response {
+        let body = ServerBusyResponse {
+            status_code: StatusCode::SERVICE_UNAVAILABLE.into(),
+            message: format!("Server busy, retry after {} seconds", self.retry_after_secs),
+            retry_after: self.retry_after_secs,
+        };
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, self.retry_after_secs.to_string())],
+            Json(body),
+        )
+            .into_response()
+    }
+}
+
+impl IntoResponse for UnknownVersion {
+    fn into_response(self) -> Response {
+        let body = UnknownVersionResponse {
+            status_code: StatusCode::BAD_REQUEST.into(),
+            message: format!("Unknown compiler version {}", self.version),
+            versions: self.versions,
+        };
+        (StatusCode::BAD_REQUEST, Json(body)).into_response()
+    }
+}
+
+/// Runs are recorded as submissions when the request carries a valid Bearer token.
 pub async fn compile_code(
+    caller: MaybeLoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    extract::Extension(config): extract::Extension<Arc<RunConfig>>,
+    extract::Extension(queue): extract::Extension<Arc<RunQueue>>,
+    extract::Extension(cache): extract::Extension<Arc<RunCache>>,
+    extract::Extension(compilers): extract::Extension<Arc<Compilers>>,
     extract::Json(user): extract::Json<CodeCompileRequest>,
-) -> Json<CodeOutputResponse> {
-    Json(CodeOutputResponse {
-        output: runnable_code(user.code, &user.input),
+) -> Result<Json<CodeOutputResponse>, Response> {
+    let username = caller.username();
+    if user.debug && username.is_none() {
+        return Err(unauthorized(DEBUG_NEEDS_LOGIN));
+    }
+    let compiler = compilers
+        .get(user.version.as_deref())
+        .map_err(IntoResponse::into_response)?;
+    let response = run_code(&state, &cache, compiler, username, user, &config, &queue)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    Ok(Json(response))
+}
+
+/// Queues and runs one program, recording it as a submission when `username` is known.
+///
+/// A run found in `cache` is answered straight away, without waiting for a worker.
+pub(crate) async fn run_code(
+    state: &MyState,
+    cache: &RunCache,
+    compiler: &Compiler,
+    username: Option<String>,
+    request: CodeCompileRequest,
+    config: &RunConfig,
+    queue: &RunQueue,
+) -> Result<CodeOutputResponse, QueueFull> {
+    let cache = usable_cache(cache, &request);
+    if let Some(execution) =
+        cache.and_then(|cache| cache.get(compiler, &request.code, &request.input, config))
+    {
+        return Ok(output_response(
+            state,
+            username,
+            compiler,
+            request.code,
+            execution,
+            CACHED,
+            true,
+        )
+        .await);
+    }
+    let ticket = queue.enter().await?;
+    let execution = runnable_code(
+        compiler,
+        &request.code,
+        &request.input,
+        request.debug,
+        config,
+    )
+    .await;
+    if let Some(cache) = cache {
+        cache.insert(compiler, &request.code, &request.input, config, &execution);
+    }
+    Ok(output_response(
+        state,
+        username,
+        compiler,
+        request.code,
+        execution,
+        ticket.info(),
+        false,
+    )
+    .await)
+}
+
+/// Runs several programs in order for one request, e.g. all examples of a lesson page.
+///
+/// Cached runs are answered without a worker; the rest share a single place in the queue, so a
+/// batch is never turned away halfway through once it starts running.
+pub async fn compile_batch(
+    caller: MaybeLoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    extract::Extension(config): extract::Extension<Arc<RunConfig>>,
+    extract::Extension(queue): extract::Extension<Arc<RunQueue>>,
+    extract::Extension(cache): extract::Extension<Arc<RunCache>>,
+    extract::Extension(compilers): extract::Extension<Arc<Compilers>>,
+    extract::Json(batch): extract::Json<CodeBatchRequest>,
+) -> Result<Json<BatchResponse>, Response> {
+    let username = caller.username();
+    if batch.runs.len() > config.max_batch_size {
+        let body = BatchTooLargeResponse {
+            status_code: StatusCode::PAYLOAD_TOO_LARGE.into(),
+            message: format!("A batch holds at most {} runs", config.max_batch_size),
+            max_batch_size: config.max_batch_size,
+        };
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, Json(body)).into_response());
+    }
+    if username.is_none() && batch.runs.iter().any(|run| run.debug) {
+        return Err(unauthorized(DEBUG_NEEDS_LOGIN));
+    }
+    // Every version is checked up front, so a batch does not fail after running part of it
+    let compilers = batch
+        .runs
+        .iter()
+        .map(|run| compilers.get(run.version.as_deref()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(IntoResponse::into_response)?;
+
+    let mut ticket: Option<Ticket> = None;
+    let mut results = Vec::with_capacity(batch.runs.len());
+    for (run, compiler) in batch.runs.into_iter().zip(compilers) {
+        let cache = usable_cache(&cache, &run);
+        if let Some(execution) =
+            cache.and_then(|cache| cache.get(compiler, &run.code, &run.input, &config))
+        {
+            let response = output_response(
+                &state,
+                username.clone(),
+                compiler,
+                run.code,
+                execution,
+                CACHED,
+                true,
+            )
+            .await;
+            results.push(response);
+            continue;
+        }
+        let queue_info = match &ticket {
+            Some(ticket) => ticket.info(),
+            None => {
+                let entered = queue.enter().await.map_err(IntoResponse::into_response)?;
+                let queue_info = entered.info();
+                ticket = Some(entered);
+                queue_info
+            }
+        };
+        let execution = runnable_code(compiler, &run.code, &run.input, run.debug, &config).await;
+        if let Some(cache) = cache {
+            cache.insert(compiler, &run.code, &run.input, &config, &execution);
+        }
+        let response = output_response(
+            &state,
+            username.clone(),
+            compiler,
+            run.code,
+            execution,
+            queue_info,
+            false,
+        )
+        .await;
+        results.push(response);
+    }
+    Ok(Json(BatchResponse { results }))
+}
+
+// Debug runs are never cached, since the trace is not part of a cache entry
+fn usable_cache<'a>(cache: &'a RunCache, request: &CodeCompileRequest) -> Option<&'a RunCache> {
+    (!request.no_cache && !request.debug).then_some(cache)
+}
+
+// Records the run for `username` and shapes it into the `/api/compile` response
+async fn output_response(
+    state: &MyState,
+    username: Option<String>,
+    compiler: &Compiler,
+    code: String,
+    execution: Execution,
+    queue: QueueInfo,
+    cached: bool,
+) -> CodeOutputResponse {
+    if let Some(username) = username {
+        let verdict = Verdict {
+            status: execution.status,
+            passed: None,
+            metrics: execution.metrics,
+        };
+        record(state, username, None, code, vec![verdict]).await;
+    }
+    CodeOutputResponse {
+        status: execution.status,
+        legacy_output: legacy(&execution.output),
+        output: execution.output,
+        truncated: execution.dropped_bytes > 0,
+        dropped_bytes: execution.dropped_bytes,
+        metrics: execution.metrics,
+        queue,
+        cached,
+        trace: execution.trace,
+        version: compiler.version.to_string(),
+    }
+}
+
+/// Like `compile_code`, a quiz is only recorded for authenticated users.
+pub async fn take_quiz(
+    caller: MaybeLoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    extract::Extension(config): extract::Extension<Arc<RunConfig>>,
+    extract::Extension(queue): extract::Extension<Arc<RunQueue>>,
+    extract::Json(user): extract::Json<CodeQuizRequest>,
+) -> Result<Json<QuizResponse>, Response> {
+    let username = caller.username();
+    let response = run_quiz(
+        &user.code,
+        user.testcases,
+        &user.comparator,
+        user.mode,
+        &config,
+        &queue,
+    )
+    .await
+    .map_err(IntoResponse::into_response)?;
+    if let Some(username) = username {
+        let verdicts = response.results.iter().map(Verdict::from).collect();
+        record(&state, username, None, user.code, verdicts).await;
+    }
+    Ok(Json(response))
+}
+
+/// Queues the code and checks it against the testcases as `mode` says.
+pub(crate) async fn run_quiz(
+    code: &str,
+    testcases: Vec<Testcase>,
+    comparator: &Comparator,
+    mode: QuizMode,
+    config: &RunConfig,
+    queue: &RunQueue,
+) -> Result<QuizResponse, QueueFull> {
+    // The whole quiz is one job, so a busy server never rejects it halfway through
+    let ticket = queue.enter().await?;
+    let helpers = match mode {
+        QuizMode::Parallel => queue.borrow_idle(config.workers - 1),
+        _ => queue.borrow_idle(0),
+    };
+    let total = testcases.len();
+    let results = match_outputs(code, testcases, comparator, mode, 1 + helpers.len(), config).await;
+    drop(helpers);
+    Ok(QuizResponse {
+        output_match: results
+            .iter()
+            .map(|result| legacy(&result.passed))
+            .collect(),
+        score: score(&results),
+        skipped: total - results.len(),
+        results,
+        queue: ticket.info(),
     })
 }
 
-pub async fn take_quiz(extract::Json(user): extract::Json<CodeQuizRequest>) -> Json<QuizResponse> {
-    Json(QuizResponse {
-        output_match: match_outputs(user.code, user.testcases),
+pub async fn versions(
+    extract::Extension(compilers): extract::Extension<Arc<Compilers>>,
+) -> Json<VersionsResponse> {
+    Json(VersionsResponse {
+        default: Compiler::BUNDLED.version.to_string(),
+        versions: compilers.versions(),
     })
 }
 
-fn runnable_code(code: String, input: &str) -> Result<String, String> {
-    let runnable = run_program(code, input, false);
-    match runnable {
-        Ok(output) => Ok(output),
-        Err(err) => Err(format!("[ERROR]\n{}\n{}", err.msg, err.error_type)),
+pub async fn cache_stats(
+    extract::Extension(cache): extract::Extension<Arc<RunCache>>,
+) -> Json<CacheStats> {
+    Json(cache.stats())
+}
+
+pub async fn queue_status(
+    extract::Extension(queue): extract::Extension<Arc<RunQueue>>,
+) -> Json<QueueStatus> {
+    Json(queue.status())
+}
+
+pub(crate) async fn runnable_code(
+    compiler: &Compiler,
+    code: &str,
+    input: &str,
+    debug: bool,
+    config: &RunConfig,
+) -> Execution {
+    let run = sandbox::run(compiler, code, input, debug, config).await;
+    let (status, output, dropped_bytes) = match run.result {
+        RunResult::Output {
+            output,
+            dropped_bytes,
+        } => (RunStatus::Success, Ok(output), dropped_bytes),
+        RunResult::Error { msg, error_type } => (
+            RunStatus::Error,
+            Err(Diagnostic::from_zen_error(msg, error_type)),
+            0,
+        ),
+        RunResult::RuntimeError(msg) => (
+            RunStatus::RuntimeError,
+            Err(Diagnostic::from_run_failure("RuntimeError", msg)),
+            0,
+        ),
+        RunResult::TimeLimitExceeded => (
+            RunStatus::TimeLimitExceeded,
+            Err(Diagnostic::from_run_failure(
+                "TimeLimitExceeded",
+                format!(
+                    "Time limit exceeded\nProgram did not finish within {} ms",
+                    config.time_limit.as_millis()
+                ),
+            )),
+            0,
+        ),
+    };
+    Execution {
+        status,
+        output,
+        dropped_bytes,
+        metrics: run.metrics,
+        trace: run.trace,
     }
 }
 
-fn match_outputs(code: String, testcases: Vec<Testcase>) -> Vec<Result<bool, String>> {
-    let mut output_vec: Vec<Result<bool, String>> = vec![];
+async fn match_outputs(
+    code: &str,
+    testcases: Vec<Testcase>,
+    default_comparator: &Comparator,
+    mode: QuizMode,
+    parallelism: usize,
+    config: &RunConfig,
+) -> Vec<TestcaseResult> {
+    let evaluations = testcases
+        .into_iter()
+        .map(|testcase| evaluate(code, testcase, default_comparator, config));
 
-    for testcase in testcases.iter() {
-        let code_instance = runnable_code(code.clone(), &testcase.input);
+    if mode == QuizMode::Parallel {
+        // `buffered` starts up to `parallelism` runs at once but yields them in order
+        return stream::iter(evaluations)
+            .buffered(parallelism)
+            .collect()
+            .await;
+    }
 
-        match code_instance {
-            Ok(actual_output) => {
-                output_vec.push(Ok(actual_output.trim_end() == testcase.expected_output))
-            }
-            Err(err) => output_vec.push(Err(err)),
+    let mut output_vec: Vec<TestcaseResult> = vec![];
+    for run in evaluations {
+        let result = run.await;
+        let failed = result.verdict != QuizVerdict::Accepted;
+        output_vec.push(result);
+        if failed && mode == QuizMode::StopAtFirstFailure {
+            break;
         }
     }
     output_vec
 }
+
+async fn evaluate(
+    code: &str,
+    testcase: Testcase,
+    default_comparator: &Comparator,
+    config: &RunConfig,
+) -> TestcaseResult {
+    // Quizzes and problems are graded on the bundled compiler
+    let execution = runnable_code(&Compiler::BUNDLED, code, &testcase.input, false, config).await;
+
+    let comparator = testcase
+        .comparator
+        .unwrap_or_else(|| default_comparator.clone());
+    let passed = match &execution.output {
+        Ok(actual_output) => comparator
+            .matches(actual_output, &testcase.expected_output)
+            .map_err(|msg| {
+                // The message may quote `expected_output`, which hidden testcases keep secret
+                let msg = if testcase.visible {
+                    msg
+                } else {
+                    HIDDEN_COMPARATOR_ERROR.to_string()
+                };
+                Diagnostic::from_run_failure("ComparatorError", msg)
+            }),
+        Err(err) => Err(err.clone()),
+    };
+    let details = testcase.visible.then(|| {
+        let actual_output = execution.output.ok();
+        // Other comparators accept outputs that differ character by character, so the first
+        // such difference would point at something that is not why the testcase failed
+        let difference = match (&passed, &actual_output, &comparator) {
+            // Exact ignores trailing whitespace at the end of the output, so the diff does too
+            (Ok(false), Some(actual_output), Comparator::Exact) => {
+                first_difference(actual_output.trim_end(), &testcase.expected_output)
+            }
+            _ => None,
+        };
+        TestcaseDetails {
+            expected_output: testcase.expected_output,
+            actual_output,
+            difference,
+        }
+    });
+    TestcaseResult {
+        status: execution.status,
+        verdict: QuizVerdict::of(execution.status, &passed),
+        passed,
+        weight: testcase.weight,
+        group: testcase.group,
+        comparator,
+        truncated: execution.dropped_bytes > 0,
+        dropped_bytes: execution.dropped_bytes,
+        metrics: execution.metrics,
+        details,
+    }
+}
+
+fn legacy<T: Clone>(result: &Result<T, Diagnostic>) -> Result<T, String> {
+    result
+        .clone()
+        .map_err(|diagnostic| diagnostic.legacy().to_string())
+}
@@ -0,0 +1,105 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+
+lazy_static! {
+    // Whole words only, so e.g. "newline" or "colon" are not read as positions
+    static ref LINE: Regex = Regex::new(r"(?i)\bline\b[ :=#]*(\d+)").unwrap();
+    static ref COLUMN: Regex = Regex::new(r"(?i)\bcol(?:umn)?\b[ :=#]*(\d+)").unwrap();
+}
+
+/// A compile or runtime error in a shape the editor can place in the source.
+///
+/// `line` and `column` are 1-based and only present when the zen error message names them.
+#[derive(Serialize, Clone, Debug)]
+pub struct Diagnostic {
+    pub kind: String,
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    #[serde(skip)]
+    legacy: String,
+}
+
+impl Diagnostic {
+    /// An error reported by the zen interpreter itself.
+    pub fn from_zen_error(msg: String, error_type: String) -> Self {
+        Diagnostic {
+            line: number_after(&msg, &LINE),
+            column: number_after(&msg, &COLUMN),
+            legacy: format!("[ERROR]\n{}\n{}", msg, error_type),
+            kind: error_type,
+            message: msg,
+        }
+    }
+
+    /// A failure of the run itself rather than of the program, e.g. a crash or a limit.
+    pub fn from_run_failure(kind: &str, msg: String) -> Self {
+        Diagnostic {
+            kind: kind.to_string(),
+            line: None,
+            column: None,
+            legacy: format!("[ERROR]\n{}", msg),
+            message: msg,
+        }
+    }
+
+    /// The preformatted "[ERROR]" string the API returned before diagnostics were structured.
+    pub fn legacy(&self) -> &str {
+        &self.legacy
+    }
+}
+
+// Finds e.g. "line 4", "Line: 4" or "col=12" in an error message
+fn number_after(msg: &str, keyword: &Regex) -> Option<usize> {
+    keyword
+        .captures_iter(msg)
+        .find_map(|captures| captures[1].parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Diagnostic;
+
+    fn position(msg: &str) -> (Option<usize>, Option<usize>) {
+        let diagnostic = Diagnostic::from_zen_error(msg.to_string(), "SyntaxError".to_string());
+        (diagnostic.line, diagnostic.column)
+    }
+
+    #[test]
+    fn reads_line_and_column() {
+        assert_eq!(
+            position("Unexpected token '}' at line 3, column 14"),
+            (Some(3), Some(14))
+        );
+        assert_eq!(
+            position("Line: 7 Col: 2: unexpected end of input"),
+            (Some(7), Some(2))
+        );
+        assert_eq!(
+            position("undefined variable `x` (line=12, col=5)"),
+            (Some(12), Some(5))
+        );
+        assert_eq!(position("Invalid indentation on line #9"), (Some(9), None));
+    }
+
+    #[test]
+    fn ignores_words_that_only_contain_the_keywords() {
+        assert_eq!(position("Expected newline 4 tokens ago"), (None, None));
+        assert_eq!(position("Expected colon 2 after key"), (None, None));
+        assert_eq!(
+            position("Expected colon after key at line 2"),
+            (Some(2), None)
+        );
+        assert_eq!(
+            position("Missing newline at line 4, column 1"),
+            (Some(4), Some(1))
+        );
+    }
+
+    #[test]
+    fn leaves_messages_without_a_position_alone() {
+        assert_eq!(position("Division by zero"), (None, None));
+        assert_eq!(position("Expected a line number"), (None, None));
+    }
+}
@@ -0,0 +1,96 @@
+use serde::Serialize;
+
+/// Where the actual output first departs from the expected one. `line` and `column` are 1-based
+/// and count characters; a missing line is shown as `None`.
+#[derive(Serialize, Debug)]
+pub struct Difference {
+    pub line: usize,
+    pub column: usize,
+    pub expected_line: Option<String>,
+    pub actual_line: Option<String>,
+}
+
+/// Compares line by line. Lines are split on `\n` only, so a `\r` left at the end of a line is a
+/// difference, as it is for the exact comparator.
+pub fn first_difference(actual: &str, expected: &str) -> Option<Difference> {
+    let mut actual_lines = actual.split('\n');
+    let mut expected_lines = expected.split('\n');
+    let mut line = 0;
+    loop {
+        line += 1;
+        match (actual_lines.next(), expected_lines.next()) {
+            (None, None) => return None,
+            (Some(actual_line), Some(expected_line)) if actual_line == expected_line => continue,
+            (actual_line, expected_line) => {
+                let column = match (actual_line, expected_line) {
+                    (Some(actual_line), Some(expected_line)) => {
+                        actual_line
+                            .chars()
+                            .zip(expected_line.chars())
+                            .take_while(|(a, e)| a == e)
+                            .count()
+                            + 1
+                    }
+                    _ => 1,
+                };
+                return Some(Difference {
+                    line,
+                    column,
+                    expected_line: expected_line.map(str::to_string),
+                    actual_line: actual_line.map(str::to_string),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::first_difference;
+
+    #[test]
+    fn equal_outputs_have_no_difference() {
+        assert!(first_difference("1\n2", "1\n2").is_none());
+        assert!(first_difference("", "").is_none());
+    }
+
+    #[test]
+    fn windows_line_endings_differ() {
+        let difference = first_difference("1\r\n2", "1\n2").unwrap();
+        assert_eq!((difference.line, difference.column), (1, 2));
+        assert_eq!(difference.actual_line.as_deref(), Some("1\r"));
+    }
+
+    #[test]
+    fn points_at_the_first_differing_char() {
+        let difference = first_difference("1\nhello world\n3", "1\nhello there\n3").unwrap();
+        assert_eq!((difference.line, difference.column), (2, 7));
+        assert_eq!(difference.expected_line.as_deref(), Some("hello there"));
+        assert_eq!(difference.actual_line.as_deref(), Some("hello world"));
+    }
+
+    #[test]
+    fn counts_columns_in_chars() {
+        let difference = first_difference("héllo", "hélp").unwrap();
+        assert_eq!((difference.line, difference.column), (1, 4));
+    }
+
+    #[test]
+    fn reports_missing_and_extra_lines() {
+        let missing = first_difference("1", "1\n2").unwrap();
+        assert_eq!((missing.line, missing.column), (2, 1));
+        assert_eq!(missing.expected_line.as_deref(), Some("2"));
+        assert_eq!(missing.actual_line, None);
+
+        let extra = first_difference("1\n2", "1").unwrap();
+        assert_eq!(extra.line, 2);
+        assert_eq!(extra.expected_line, None);
+        assert_eq!(extra.actual_line.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn a_prefix_differs_after_its_last_char() {
+        let difference = first_difference("abc", "abcd").unwrap();
+        assert_eq!((difference.line, difference.column), (1, 4));
+    }
+}
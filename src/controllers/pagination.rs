@@ -0,0 +1,102 @@
+use serde::Deserialize;
+use shuttle_persist::{PersistError, PersistInstance};
+
+use crate::controllers::authentication::MyState;
+
+const DEFAULT_PER_PAGE: usize = 20;
+const MAX_PER_PAGE: usize = 100;
+
+#[derive(Deserialize)]
+pub struct Pagination {
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+impl Pagination {
+    /// The 1-based page number and the page size, clamped to what the API allows.
+    pub(crate) fn bounds(&self) -> (usize, usize) {
+        let page = self.page.unwrap_or(1).max(1);
+        let per_page = self
+            .per_page
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE);
+        (page, per_page)
+    }
+
+    pub(crate) fn page_of<T>(&self, items: Vec<T>) -> Vec<T> {
+        let (page, per_page) = self.bounds();
+        items
+            .into_iter()
+            .skip((page - 1).saturating_mul(per_page))
+            .take(per_page)
+            .collect()
+    }
+}
+
+/// One page of a listing and the number of entries on all pages.
+pub(crate) struct Page<T> {
+    pub(crate) total: usize,
+    pub(crate) items: Vec<T>,
+}
+
+/// Pages through an index of ids kept oldest first, newest first. Only the entries on the
+/// requested page are loaded, and an entry that cannot be loaded fails the whole page.
+pub(crate) async fn list_page<Id, T>(
+    state: &MyState,
+    pagination: Pagination,
+    ids: impl FnOnce(&PersistInstance) -> Result<Vec<Id>, PersistError> + Send + 'static,
+    load: impl Fn(&PersistInstance, Id) -> Result<T, PersistError> + Send + 'static,
+) -> Result<Page<T>, PersistError>
+where
+    Id: Send + 'static,
+    T: Send + 'static,
+{
+    state
+        .blocking(move |persist| {
+            let mut ids = ids(persist)?;
+            ids.reverse();
+            let total = ids.len();
+            let items = pagination
+                .page_of(ids)
+                .into_iter()
+                .map(|id| load(persist, id))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Page { total, items })
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Pagination, DEFAULT_PER_PAGE, MAX_PER_PAGE};
+
+    fn pagination(page: Option<usize>, per_page: Option<usize>) -> Pagination {
+        Pagination { page, per_page }
+    }
+
+    #[test]
+    fn pages_default_and_clamp() {
+        assert_eq!(pagination(None, None).bounds(), (1, DEFAULT_PER_PAGE));
+        assert_eq!(pagination(Some(0), Some(0)).bounds(), (1, 1));
+        assert_eq!(
+            pagination(Some(3), Some(MAX_PER_PAGE + 1)).bounds(),
+            (3, MAX_PER_PAGE)
+        );
+    }
+
+    #[test]
+    fn page_of_returns_the_requested_slice() {
+        let items: Vec<u64> = (1..=7).collect();
+        assert_eq!(
+            pagination(None, Some(3)).page_of(items.clone()),
+            vec![1, 2, 3]
+        );
+        assert_eq!(pagination(Some(3), Some(3)).page_of(items.clone()), vec![7]);
+        assert!(pagination(Some(4), Some(3))
+            .page_of(items.clone())
+            .is_empty());
+        assert!(pagination(Some(usize::MAX), Some(3))
+            .page_of(items)
+            .is_empty());
+    }
+}
@@ -0,0 +1,454 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{self, Path},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use http::StatusCode;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use shuttle_persist::{PersistError, PersistInstance};
+use tower_http::add_extension::AddExtensionLayer;
+
+use crate::{
+    controllers::{
+        authentication::{Admin, MaybeLoggedIn, MyState},
+        comparator::Comparator,
+        compile_code::{run_quiz, QuizMode, Testcase},
+        submissions::{record, Verdict},
+    },
+    run_config::RunConfig,
+    run_queue::RunQueue,
+};
+
+pub const PROBLEMS_KEY: &str = "problems";
+
+lazy_static! {
+    static ref BANK_LOCK: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ProblemBank {
+    pub(crate) problems: Vec<Problem>,
+    pub(crate) next_id: u64,
+}
+
+impl ProblemBank {
+    pub fn new() -> Self {
+        Self {
+            problems: Vec::new(),
+            next_id: 1,
+        }
+    }
+}
+
+/// An example shown to students together with the statement.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Sample {
+    pub input: String,
+    pub expected_output: String,
+}
+
+/// Everything an admin sends to create or update a problem.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ProblemSpec {
+    pub title: String,
+    pub statement: String,
+    #[serde(default)]
+    pub samples: Vec<Sample>,
+    /// Never shown to students; only pass/fail is reported for them.
+    pub testcases: Vec<Testcase>,
+    /// Overrides the server's `RUN_TIME_LIMIT_MS` for this problem.
+    pub time_limit_ms: Option<u64>,
+    #[serde(default)]
+    pub comparator: Comparator,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Problem {
+    pub id: u64,
+    #[serde(flatten)]
+    pub spec: ProblemSpec,
+}
+
+#[derive(Serialize)]
+pub struct ProblemSummary {
+    id: u64,
+    title: String,
+}
+
+/// What students get to see of a problem.
+#[derive(Serialize)]
+pub struct PublicProblem {
+    id: u64,
+    title: String,
+    statement: String,
+    samples: Vec<Sample>,
+    time_limit_ms: Option<u64>,
+    comparator: Comparator,
+    hidden_testcases: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProblemResponse<T> {
+    status_code: u16,
+    message: String,
+    problem: Option<T>,
+}
+
+#[derive(Serialize)]
+pub struct ProblemListResponse {
+    status_code: u16,
+    message: String,
+    problems: Vec<ProblemSummary>,
+}
+
+#[derive(Deserialize)]
+pub struct SubmitRequest {
+    code: String,
+    #[serde(default)]
+    mode: QuizMode,
+}
+
+impl From<&Problem> for PublicProblem {
+    fn from(problem: &Problem) -> Self {
+        PublicProblem {
+            id: problem.id,
+            title: problem.spec.title.clone(),
+            statement: problem.spec.statement.clone(),
+            samples: problem.spec.samples.clone(),
+            time_limit_ms: problem.spec.time_limit_ms,
+            comparator: problem.spec.comparator.clone(),
+            hidden_testcases: problem.spec.testcases.len(),
+        }
+    }
+}
+
+/// Creates the problem bank on first start. A bank that exists but cannot be read stops startup
+/// instead of being replaced by an empty one.
+pub fn init(persist: &PersistInstance) {
+    match persist.load::<ProblemBank>(PROBLEMS_KEY) {
+        Ok(_) => {}
+        Err(PersistError::Open(e)) if e.kind() == io::ErrorKind::NotFound => persist
+            .save::<ProblemBank>(PROBLEMS_KEY, ProblemBank::new())
+            .unwrap(),
+        Err(e) => panic!("Cannot load the problem bank: {}", e),
+    }
+}
+
+pub fn problem_routes(state: Arc<MyState>) -> Router {
+    Router::new()
+        .route("/problems", get(list_problems).post(create_problem))
+        .route(
+            "/problems/:id",
+            get(get_problem).put(update_problem).delete(delete_problem),
+        )
+        .route("/problems/:id/full", get(get_full_problem))
+        .route("/problems/:id/submit", post(submit_problem))
+        .layer(AddExtensionLayer::new(state))
+}
+
+pub async fn list_problems(
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+) -> Json<ProblemListResponse> {
+    match state
+        .blocking(|persist| persist.load::<ProblemBank>(PROBLEMS_KEY))
+        .await
+    {
+        Ok(bank) => Json(ProblemListResponse {
+            status_code: StatusCode::OK.into(),
+            message: "Problems fetched successfully".to_string(),
+            problems: bank
+                .problems
+                .iter()
+                .map(|problem| ProblemSummary {
+                    id: problem.id,
+                    title: problem.spec.title.clone(),
+                })
+                .collect(),
+        }),
+        Err(e) => Json(ProblemListResponse {
+            status_code: StatusCode::INTERNAL_SERVER_ERROR.into(),
+            message: e.to_string(),
+            problems: Vec::new(),
+        }),
+    }
+}
+
+pub async fn get_problem(
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Path(id): Path<u64>,
+) -> Json<ProblemResponse<PublicProblem>> {
+    let bank = match load_bank(&state).await {
+        Ok(bank) => bank,
+        Err(response) => return response,
+    };
+    match bank.problems.iter().find(|problem| problem.id == id) {
+        Some(problem) => respond(StatusCode::OK, "Problem found", Some(problem.into())),
+        None => respond(StatusCode::NOT_FOUND, "Problem not found.", None),
+    }
+}
+
+pub async fn get_full_problem(
+    _admin: Admin,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Path(id): Path<u64>,
+) -> Json<ProblemResponse<Problem>> {
+    let bank = match load_bank(&state).await {
+        Ok(bank) => bank,
+        Err(response) => return response,
+    };
+    match bank.problems.into_iter().find(|problem| problem.id == id) {
+        Some(problem) => respond(StatusCode::OK, "Problem found", Some(problem)),
+        None => respond(StatusCode::NOT_FOUND, "Problem not found.", None),
+    }
+}
+
+pub async fn create_problem(
+    _admin: Admin,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Json(spec): Json<ProblemSpec>,
+) -> Json<ProblemResponse<Problem>> {
+    if let Err(message) = validate(&spec) {
+        return respond(StatusCode::BAD_REQUEST, message, None);
+    }
+    modify_bank(
+        &state,
+        StatusCode::CREATED,
+        "Problem created successfully",
+        move |bank| {
+            let problem = Problem {
+                id: bank.next_id,
+                spec,
+            };
+            bank.next_id += 1;
+            bank.problems.push(problem.clone());
+            Ok(problem)
+        },
+    )
+    .await
+}
+
+pub async fn update_problem(
+    _admin: Admin,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Path(id): Path<u64>,
+    Json(spec): Json<ProblemSpec>,
+) -> Json<ProblemResponse<Problem>> {
+    if let Err(message) = validate(&spec) {
+        return respond(StatusCode::BAD_REQUEST, message, None);
+    }
+    modify_bank(
+        &state,
+        StatusCode::OK,
+        "Problem updated successfully",
+        move |bank| match bank.problems.iter_mut().find(|problem| problem.id == id) {
+            Some(problem) => {
+                problem.spec = spec;
+                Ok(problem.clone())
+            }
+            None => Err((StatusCode::NOT_FOUND, "Problem not found.")),
+        },
+    )
+    .await
+}
+
+pub async fn delete_problem(
+    _admin: Admin,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Path(id): Path<u64>,
+) -> Json<ProblemResponse<Problem>> {
+    modify_bank(
+        &state,
+        StatusCode::OK,
+        "Problem deleted successfully",
+        move |bank| match bank.problems.iter().position(|problem| problem.id == id) {
+            Some(index) => Ok(bank.problems.remove(index)),
+            None => Err((StatusCode::NOT_FOUND, "Problem not found.")),
+        },
+    )
+    .await
+}
+
+/// Grades code against a stored problem: samples are reported in full, hidden testcases only
+/// as pass/fail. Authenticated submissions are recorded.
+pub async fn submit_problem(
+    caller: MaybeLoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    extract::Extension(config): extract::Extension<Arc<RunConfig>>,
+    extract::Extension(queue): extract::Extension<Arc<RunQueue>>,
+    Path(id): Path<u64>,
+    Json(req): Json<SubmitRequest>,
+) -> Response {
+    let username = caller.username();
+    let bank = match load_bank::<()>(&state).await {
+        Ok(bank) => bank,
+        Err(response) => return response.into_response(),
+    };
+    let problem = match bank.problems.into_iter().find(|problem| problem.id == id) {
+        Some(problem) => problem,
+        None => {
+            return respond::<()>(StatusCode::NOT_FOUND, "Problem not found.", None)
+                .into_response();
+        }
+    };
+
+    let mut config = RunConfig::clone(&config);
+    if let Some(time_limit_ms) = problem.spec.time_limit_ms {
+        config.time_limit = Duration::from_millis(time_limit_ms);
+        // The CPU limit would otherwise kill the run before a longer time limit is reached
+        config.cpu_limit_secs = config.cpu_limit_secs.max(time_limit_ms.div_ceil(1000));
+    }
+
+    match run_quiz(
+        &req.code,
+        problem_testcases(&problem),
+        &problem.spec.comparator,
+        req.mode,
+        &config,
+        &queue,
+    )
+    .await
+    {
+        Ok(response) => {
+            if let Some(username) = username {
+                let verdicts = response.results.iter().map(Verdict::from).collect();
+                record(&state, username, Some(problem.id), req.code, verdicts).await;
+            }
+            Json(response).into_response()
+        }
+        Err(full) => full.into_response(),
+    }
+}
+
+/// The samples (visible, worth no points) followed by the hidden testcases of a problem.
+pub(crate) fn problem_testcases(problem: &Problem) -> Vec<Testcase> {
+    let samples = problem.spec.samples.iter().map(|sample| Testcase {
+        input: sample.input.clone(),
+        expected_output: sample.expected_output.clone(),
+        comparator: None,
+        visible: true,
+        weight: 0.0,
+        group: "samples".to_string(),
+    });
+    let hidden = problem.spec.testcases.iter().map(|testcase| Testcase {
+        visible: false,
+        ..testcase.clone()
+    });
+    samples.chain(hidden).collect()
+}
+
+fn validate(spec: &ProblemSpec) -> Result<(), String> {
+    if spec.title.trim().is_empty() {
+        return Err("Title cannot be empty".to_string());
+    }
+    if spec.testcases.is_empty() {
+        return Err("A problem needs at least one testcase".to_string());
+    }
+    // Samples are graded with the problem's comparator, so they need checking as well
+    for (number, sample) in spec.samples.iter().enumerate() {
+        spec.comparator
+            .check(&sample.expected_output)
+            .map_err(|e| format!("Sample {}: {}", number + 1, e))?;
+    }
+    for (number, testcase) in spec.testcases.iter().enumerate() {
+        testcase
+            .comparator
+            .as_ref()
+            .unwrap_or(&spec.comparator)
+            .check(&testcase.expected_output)
+            .map_err(|e| format!("Testcase {}: {}", number + 1, e))?;
+    }
+    Ok(())
+}
+
+async fn load_bank<T>(state: &MyState) -> Result<ProblemBank, Json<ProblemResponse<T>>> {
+    state
+        .blocking(|persist| persist.load::<ProblemBank>(PROBLEMS_KEY))
+        .await
+        .map_err(|e| respond(StatusCode::INTERNAL_SERVER_ERROR, e.to_string(), None))
+}
+
+// Admin changes load, modify and save the whole bank, so they hold the lock throughout
+async fn modify_bank(
+    state: &MyState,
+    status: StatusCode,
+    message: &'static str,
+    change: impl FnOnce(&mut ProblemBank) -> Result<Problem, (StatusCode, &'static str)>
+        + Send
+        + 'static,
+) -> Json<ProblemResponse<Problem>> {
+    let modified = state
+        .blocking(move |persist| {
+            let _guard = BANK_LOCK
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let mut bank = persist.load::<ProblemBank>(PROBLEMS_KEY)?;
+            let problem = match change(&mut bank) {
+                Ok(problem) => problem,
+                Err(rejected) => return Ok(Err(rejected)),
+            };
+            persist.save::<ProblemBank>(PROBLEMS_KEY, bank)?;
+            Ok::<_, PersistError>(Ok(problem))
+        })
+        .await;
+    match modified {
+        Ok(Ok(problem)) => respond(status, message, Some(problem)),
+        Ok(Err((status, message))) => respond(status, message, None),
+        Err(e) => respond(StatusCode::INTERNAL_SERVER_ERROR, e.to_string(), None),
+    }
+}
+
+fn respond<T>(
+    status: StatusCode,
+    message: impl Into<String>,
+    problem: Option<T>,
+) -> Json<ProblemResponse<T>> {
+    Json(ProblemResponse {
+        status_code: status.into(),
+        message: message.into(),
+        problem,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate, ProblemSpec, Sample};
+    use crate::controllers::{comparator::Comparator, compile_code::Testcase};
+
+    fn spec(comparator: Comparator, testcase_comparator: Option<Comparator>) -> ProblemSpec {
+        ProblemSpec {
+            title: "Echo".to_string(),
+            statement: String::new(),
+            samples: vec![Sample {
+                input: String::new(),
+                expected_output: "ok".to_string(),
+            }],
+            testcases: vec![Testcase {
+                input: String::new(),
+                expected_output: "[0-9+".to_string(),
+                comparator: testcase_comparator,
+                visible: false,
+                weight: 1.0,
+                group: "default".to_string(),
+            }],
+            time_limit_ms: None,
+            comparator,
+        }
+    }
+
+    #[test]
+    fn rejects_patterns_that_do_not_compile() {
+        assert!(validate(&spec(Comparator::Exact, None)).is_ok());
+        let inherited = validate(&spec(Comparator::Regex, None)).unwrap_err();
+        assert!(inherited.starts_with("Testcase 1: "));
+        assert!(validate(&spec(Comparator::Exact, Some(Comparator::Regex))).is_err());
+        assert!(validate(&spec(Comparator::Regex, Some(Comparator::Exact))).is_ok());
+    }
+}
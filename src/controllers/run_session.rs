@@ -0,0 +1,277 @@
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Extension,
+    },
+    response::Response,
+};
+use serde::Deserialize;
+use tokio::time;
+
+use crate::{
+    compilers::Compiler,
+    controllers::{
+        compile_code::{runnable_code, Execution},
+        run_ws::{chunks, enter, send, RunEvent, CHUNK_BYTES},
+        scoring::QuizVerdict,
+    },
+    run_config::RunConfig,
+    run_queue::RunQueue,
+};
+
+/// Messages the client sends over `/api/run/session`, tagged by `"type"`.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionCommand {
+    Start {
+        code: String,
+        #[serde(default)]
+        input: String,
+    },
+    Stdin {
+        data: String,
+    },
+    Eof,
+}
+
+/// An interactive run: the client sends `start`, then `stdin` lines as the program asks for
+/// them, and finally `eof` to get the `exit` event.
+///
+/// `zen::run_program` takes all of its input up front, so the session replays the program with
+/// the stdin received so far after every line and streams whatever output is new.
+pub async fn run_session(
+    ws: WebSocketUpgrade,
+    Extension(config): Extension<Arc<RunConfig>>,
+    Extension(queue): Extension<Arc<RunQueue>>,
+) -> Response {
+    ws.on_upgrade(move |socket| session(socket, config, queue))
+}
+
+async fn session(mut socket: WebSocket, config: Arc<RunConfig>, queue: Arc<RunQueue>) {
+    let (code, mut input) = match next_command(&mut socket, &config).await {
+        Some(SessionCommand::Start { code, input }) => (code, input),
+        Some(_) => {
+            let message = "The first message must be a start command".to_string();
+            send(&mut socket, &RunEvent::Error { message }).await;
+            return;
+        }
+        None => return,
+    };
+
+    let mut shown = String::new();
+    let mut eof = false;
+    let mut runs = 0;
+    loop {
+        if runs == config.max_session_runs {
+            let message = format!(
+                "Session closed after {} runs; send eof sooner",
+                config.max_session_runs
+            );
+            if send(&mut socket, &RunEvent::Error { message }).await {
+                let _ = socket.send(Message::Close(None)).await;
+            }
+            return;
+        }
+
+        // Runs are bounded by the time limit, so the socket is only read again once they end;
+        // a closed socket ends the session on the next read.
+        match enter(&mut socket, &queue).await {
+            Some(Ok(ticket)) => {
+                let queue = ticket.info();
+                if !send(&mut socket, &RunEvent::Started { queue }).await {
+                    return;
+                }
+                let started = Instant::now();
+                let execution =
+                    runnable_code(&Compiler::BUNDLED, &code, &input, false, &config).await;
+                let elapsed_ms = started.elapsed().as_millis() as u64;
+                drop(ticket);
+                runs += 1;
+
+                if let Ok(output) = &execution.output {
+                    if !show(&mut socket, &mut shown, output).await {
+                        return;
+                    }
+                }
+                if eof || ends_session(&execution) {
+                    let exit = RunEvent::Exit {
+                        status: execution.status,
+                        elapsed_ms,
+                        error: execution.output.err(),
+                        truncated: execution.dropped_bytes > 0,
+                        dropped_bytes: execution.dropped_bytes,
+                        metrics: execution.metrics,
+                    };
+                    if send(&mut socket, &exit).await {
+                        let _ = socket.send(Message::Close(None)).await;
+                    }
+                    return;
+                }
+                let error = execution.output.err();
+                if !send(&mut socket, &RunEvent::AwaitingInput { error }).await {
+                    return;
+                }
+            }
+            None => return,
+            Some(Err(full)) => {
+                let retry_after = full.retry_after_secs;
+                if !send(&mut socket, &RunEvent::Busy { retry_after }).await || eof {
+                    return;
+                }
+            }
+        }
+
+        match next_command(&mut socket, &config).await {
+            Some(SessionCommand::Stdin { data }) => {
+                input.push_str(&data);
+                if !data.ends_with('\n') {
+                    input.push('\n');
+                }
+            }
+            Some(SessionCommand::Eof) => eof = true,
+            Some(SessionCommand::Start { .. }) => {
+                let message = "The session has already started".to_string();
+                if !send(&mut socket, &RunEvent::Error { message }).await {
+                    return;
+                }
+            }
+            None => return,
+        }
+    }
+}
+
+// More input cannot fix a compile error or a program that runs out of time, so those end the
+// session like `eof` does. Runtime errors, such as reading past the input sent so far, do not.
+fn ends_session(execution: &Execution) -> bool {
+    let outcome = execution
+        .output
+        .as_ref()
+        .map(|_| false)
+        .map_err(Clone::clone);
+    matches!(
+        QuizVerdict::of(execution.status, &outcome),
+        QuizVerdict::CompileError | QuizVerdict::TimeLimitExceeded
+    )
+}
+
+/// Waits for the next valid command, or `None` once the socket closes or stays idle too long.
+async fn next_command(socket: &mut WebSocket, config: &RunConfig) -> Option<SessionCommand> {
+    loop {
+        let message = match time::timeout(config.session_idle_timeout, socket.recv()).await {
+            Ok(Some(Ok(message))) => message,
+            Ok(_) => return None,
+            Err(_) => {
+                let message = format!(
+                    "Session closed after {} seconds without input",
+                    config.session_idle_timeout.as_secs()
+                );
+                send(socket, &RunEvent::Error { message }).await;
+                let _ = socket.send(Message::Close(None)).await;
+                return None;
+            }
+        };
+        match message {
+            Message::Text(text) => match serde_json::from_str::<SessionCommand>(&text) {
+                Ok(command) => return Some(command),
+                Err(e) => {
+                    let message = format!("Invalid session command: {}", e);
+                    if !send(socket, &RunEvent::Error { message }).await {
+                        return None;
+                    }
+                }
+            },
+            Message::Close(_) => return None,
+            _ => {}
+        }
+    }
+}
+
+// Sends only the output the client has not seen yet, or everything after a `reset` when the
+// replay printed something different (e.g. the program took another branch with more input)
+async fn show(socket: &mut WebSocket, shown: &mut String, output: &str) -> bool {
+    let fresh = match output.strip_prefix(shown.as_str()) {
+        Some(fresh) => fresh,
+        None => {
+            if !send(socket, &RunEvent::Reset).await {
+                return false;
+            }
+            output
+        }
+    };
+    for data in chunks(fresh, CHUNK_BYTES) {
+        let data = data.to_string();
+        if !send(socket, &RunEvent::Stdout { data }).await {
+            return false;
+        }
+    }
+    *shown = output.to_string();
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ends_session;
+    use crate::{
+        controllers::{
+            compile_code::{Execution, RunStatus},
+            diagnostic::Diagnostic,
+        },
+        sandbox::Metrics,
+    };
+
+    fn execution(status: RunStatus, output: Result<String, Diagnostic>) -> Execution {
+        Execution {
+            status,
+            output,
+            dropped_bytes: 0,
+            metrics: Metrics {
+                wall_time_ms: 0,
+                cpu_time_ms: None,
+                peak_memory_kb: None,
+            },
+            trace: None,
+        }
+    }
+
+    fn zen_error(kind: &str) -> Result<String, Diagnostic> {
+        Err(Diagnostic::from_zen_error(
+            "No more input".to_string(),
+            kind.to_string(),
+        ))
+    }
+
+    #[test]
+    fn compile_errors_and_time_outs_end_the_session() {
+        assert!(ends_session(&execution(
+            RunStatus::Error,
+            zen_error("SyntaxError")
+        )));
+        let timed_out = Err(Diagnostic::from_run_failure(
+            "TimeLimitExceeded",
+            "Time limit exceeded".to_string(),
+        ));
+        assert!(ends_session(&execution(
+            RunStatus::TimeLimitExceeded,
+            timed_out
+        )));
+    }
+
+    #[test]
+    fn runtime_errors_wait_for_more_input() {
+        assert!(!ends_session(&execution(
+            RunStatus::Error,
+            zen_error("RuntimeError")
+        )));
+        let crashed = Err(Diagnostic::from_run_failure(
+            "RuntimeError",
+            "Program crashed".to_string(),
+        ));
+        assert!(!ends_session(&execution(RunStatus::RuntimeError, crashed)));
+        assert!(!ends_session(&execution(
+            RunStatus::Success,
+            Ok("Name? ".to_string())
+        )));
+    }
+}
@@ -0,0 +1,243 @@
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Extension,
+    },
+    response::Response,
+};
+use serde::Serialize;
+use tokio::time;
+
+use crate::{
+    compilers::Compilers,
+    controllers::{
+        compile_code::{runnable_code, CodeCompileRequest, RunStatus},
+        diagnostic::Diagnostic,
+    },
+    run_config::RunConfig,
+    run_queue::{QueueFull, QueueInfo, RunQueue, Ticket},
+    sandbox::Metrics,
+};
+
+// Keeps each websocket frame small enough for the browser to render progressively
+pub(crate) const CHUNK_BYTES: usize = 4096;
+
+/// Messages the server sends over `/api/run/ws`, tagged by `"type"`.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RunEvent {
+    /// Sent while the run waits for a worker; `position` 1 is next in line.
+    Queued {
+        position: usize,
+        estimated_wait_secs: u64,
+    },
+    Started {
+        queue: QueueInfo,
+    },
+    Stdout {
+        data: String,
+    },
+    /// Interactive sessions only: the output shown so far is stale and the `stdout` chunks that
+    /// follow replace it.
+    Reset,
+    /// Interactive sessions only: the program has consumed all stdin sent so far. `error` is what
+    /// stopped the replay when it did not finish, e.g. reading past the input received so far.
+    AwaitingInput {
+        error: Option<Diagnostic>,
+    },
+    Exit {
+        status: RunStatus,
+        elapsed_ms: u64,
+        error: Option<Diagnostic>,
+        truncated: bool,
+        dropped_bytes: usize,
+        metrics: Metrics,
+    },
+    Busy {
+        retry_after: u64,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Runs one program per connection: the client sends a `CodeCompileRequest` as its first text
+/// message and receives `started`, any number of `stdout` chunks and a final `exit`.
+pub async fn run_ws(
+    ws: WebSocketUpgrade,
+    Extension(config): Extension<Arc<RunConfig>>,
+    Extension(queue): Extension<Arc<RunQueue>>,
+    Extension(compilers): Extension<Arc<Compilers>>,
+) -> Response {
+    ws.on_upgrade(move |socket| run_session(socket, config, queue, compilers))
+}
+
+async fn run_session(
+    mut socket: WebSocket,
+    config: Arc<RunConfig>,
+    queue: Arc<RunQueue>,
+    compilers: Arc<Compilers>,
+) {
+    let request = match time::timeout(config.session_idle_timeout, socket.recv()).await {
+        Ok(Some(Ok(Message::Text(text)))) => serde_json::from_str::<CodeCompileRequest>(&text),
+        Ok(_) => return,
+        Err(_) => {
+            let message = format!(
+                "Connection closed after {} seconds without a run request",
+                config.session_idle_timeout.as_secs()
+            );
+            send(&mut socket, &RunEvent::Error { message }).await;
+            let _ = socket.send(Message::Close(None)).await;
+            return;
+        }
+    };
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => {
+            let message = format!("Invalid run request: {}", e);
+            send(&mut socket, &RunEvent::Error { message }).await;
+            return;
+        }
+    };
+    let compiler = match compilers.get(request.version.as_deref()) {
+        Ok(compiler) => compiler,
+        Err(unknown) => {
+            let message = format!(
+                "Unknown compiler version {}, expected one of {}",
+                unknown.version,
+                unknown.versions.join(", ")
+            );
+            send(&mut socket, &RunEvent::Error { message }).await;
+            return;
+        }
+    };
+
+    let ticket = match enter(&mut socket, &queue).await {
+        Some(Ok(ticket)) => ticket,
+        Some(Err(full)) => {
+            let retry_after = full.retry_after_secs;
+            send(&mut socket, &RunEvent::Busy { retry_after }).await;
+            return;
+        }
+        None => return,
+    };
+    let queue = ticket.info();
+    if !send(&mut socket, &RunEvent::Started { queue }).await {
+        return;
+    }
+
+    // Dropping the run when the client goes away kills the sandboxed program with it
+    let started = Instant::now();
+    let execution = tokio::select! {
+        execution = runnable_code(compiler, &request.code, &request.input, false, &config) => execution,
+        _ = closed(&mut socket) => return,
+    };
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+
+    // zen hands the output back only once the program has finished, so it is chunked from here
+    let (output, error) = match execution.output {
+        Ok(output) => (output, None),
+        Err(diagnostic) => (String::new(), Some(diagnostic)),
+    };
+    for data in chunks(&output, CHUNK_BYTES) {
+        let data = data.to_string();
+        if !send(&mut socket, &RunEvent::Stdout { data }).await {
+            return;
+        }
+    }
+
+    let exit = RunEvent::Exit {
+        status: execution.status,
+        elapsed_ms,
+        error,
+        truncated: execution.dropped_bytes > 0,
+        dropped_bytes: execution.dropped_bytes,
+        metrics: execution.metrics,
+    };
+    if send(&mut socket, &exit).await {
+        let _ = socket.send(Message::Close(None)).await;
+    }
+}
+
+/// Takes a place in line and, if there is a wait, tells the client where it stands before
+/// waiting for a worker. `None` once the client is gone.
+pub(crate) async fn enter<'a>(
+    socket: &mut WebSocket,
+    queue: &'a RunQueue,
+) -> Option<Result<Ticket<'a>, QueueFull>> {
+    let place = match queue.join() {
+        Ok(place) => place,
+        Err(full) => return Some(Err(full)),
+    };
+    if place.position() > 0 {
+        let queued = RunEvent::Queued {
+            position: place.position(),
+            estimated_wait_secs: place.estimated_wait_secs(),
+        };
+        if !send(socket, &queued).await {
+            return None;
+        }
+    }
+    Some(Ok(place.ready().await))
+}
+
+/// Sends one event, returning `false` once the client is gone.
+pub(crate) async fn send(socket: &mut WebSocket, event: &RunEvent) -> bool {
+    let text = serde_json::to_string(event).unwrap();
+    socket.send(Message::Text(text)).await.is_ok()
+}
+
+// Resolves when the client closes the socket; anything else it sends mid-run is ignored
+async fn closed(socket: &mut WebSocket) {
+    while let Some(Ok(message)) = socket.recv().await {
+        if let Message::Close(_) = message {
+            return;
+        }
+    }
+}
+
+// Splits on char boundaries into pieces of at most `max_bytes` (or one char, if wider)
+pub(crate) fn chunks(text: &str, max_bytes: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let mut end = max_bytes.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (piece, tail) = rest.split_at(end);
+        pieces.push(piece);
+        rest = tail;
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::chunks;
+
+    #[test]
+    fn chunks_split_at_the_byte_limit() {
+        assert_eq!(chunks("abcdefg", 3), vec!["abc", "def", "g"]);
+        assert_eq!(chunks("abc", 3), vec!["abc"]);
+        assert!(chunks("", 3).is_empty());
+    }
+
+    #[test]
+    fn chunks_never_split_a_char() {
+        // "é" is two bytes, so a three byte limit fits one of them per chunk
+        assert_eq!(chunks("ééé", 3), vec!["é", "é", "é"]);
+        assert_eq!(chunks("aé", 2), vec!["a", "é"]);
+        assert_eq!(chunks("ééé", 3).concat(), "ééé");
+    }
+
+    #[test]
+    fn chunks_keep_chars_wider_than_the_limit_whole() {
+        assert_eq!(chunks("🦀🦀", 2), vec!["🦀", "🦀"]);
+    }
+}
@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+
+use crate::controllers::{
+    compile_code::{RunStatus, TestcaseResult},
+    diagnostic::Diagnostic,
+};
+
+/// The judge's verdict for one testcase or, for a whole quiz, the one of its first testcase that
+/// did not pass.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum QuizVerdict {
+    Accepted,
+    WrongAnswer,
+    RuntimeError,
+    TimeLimitExceeded,
+    CompileError,
+}
+
+#[derive(Serialize, Debug)]
+pub struct GroupScore {
+    pub group: String,
+    pub score: f64,
+    pub max_score: f64,
+    pub passed: usize,
+    pub testcases: usize,
+}
+
+/// Weighted score of a quiz; a testcase earns its weight only when it passes.
+#[derive(Serialize, Debug)]
+pub struct Score {
+    pub verdict: QuizVerdict,
+    pub score: f64,
+    pub max_score: f64,
+    /// In the order each group first appears among the testcases.
+    pub groups: Vec<GroupScore>,
+}
+
+impl QuizVerdict {
+    pub fn of(status: RunStatus, passed: &Result<bool, Diagnostic>) -> Self {
+        match (status, passed) {
+            (_, Ok(true)) => QuizVerdict::Accepted,
+            (RunStatus::TimeLimitExceeded, _) => QuizVerdict::TimeLimitExceeded,
+            (RunStatus::RuntimeError, _) => QuizVerdict::RuntimeError,
+            // zen reports parse and runtime errors alike; only its error kind tells them apart
+            (RunStatus::Error, Err(diagnostic)) => match diagnostic.kind.as_str() {
+                "RuntimeError" => QuizVerdict::RuntimeError,
+                _ => QuizVerdict::CompileError,
+            },
+            (RunStatus::Error, Ok(_)) => QuizVerdict::CompileError,
+            // A wrong output, or a testcase whose comparator could not be applied
+            (RunStatus::Success, _) => QuizVerdict::WrongAnswer,
+        }
+    }
+}
+
+pub fn score(results: &[TestcaseResult]) -> Score {
+    let mut groups: Vec<GroupScore> = Vec::new();
+    for result in results {
+        let index = match groups.iter().position(|group| group.group == result.group) {
+            Some(index) => index,
+            None => {
+                groups.push(GroupScore {
+                    group: result.group.clone(),
+                    score: 0.0,
+                    max_score: 0.0,
+                    passed: 0,
+                    testcases: 0,
+                });
+                groups.len() - 1
+            }
+        };
+        let group = &mut groups[index];
+        group.max_score += result.weight;
+        group.testcases += 1;
+        if result.verdict == QuizVerdict::Accepted {
+            group.score += result.weight;
+            group.passed += 1;
+        }
+    }
+
+    Score {
+        verdict: results
+            .iter()
+            .map(|result| result.verdict)
+            .find(|verdict| *verdict != QuizVerdict::Accepted)
+            .unwrap_or(QuizVerdict::Accepted),
+        score: groups.iter().map(|group| group.score).sum(),
+        max_score: groups.iter().map(|group| group.max_score).sum(),
+        groups,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{controllers::comparator::Comparator, sandbox::Metrics};
+
+    fn result(group: &str, weight: f64, verdict: QuizVerdict) -> TestcaseResult {
+        TestcaseResult {
+            status: RunStatus::Success,
+            verdict,
+            passed: Ok(verdict == QuizVerdict::Accepted),
+            weight,
+            group: group.to_string(),
+            comparator: Comparator::Exact,
+            truncated: false,
+            dropped_bytes: 0,
+            metrics: Metrics {
+                wall_time_ms: 0,
+                cpu_time_ms: None,
+                peak_memory_kb: None,
+            },
+            details: None,
+        }
+    }
+
+    fn zen_error(kind: &str) -> Result<bool, Diagnostic> {
+        Err(Diagnostic::from_zen_error(
+            "boom".to_string(),
+            kind.to_string(),
+        ))
+    }
+
+    #[test]
+    fn zen_error_kinds_map_to_verdicts() {
+        assert_eq!(
+            QuizVerdict::of(RunStatus::Error, &zen_error("RuntimeError")),
+            QuizVerdict::RuntimeError
+        );
+        assert_eq!(
+            QuizVerdict::of(RunStatus::Error, &zen_error("SyntaxError")),
+            QuizVerdict::CompileError
+        );
+        // Only the exact kind counts, not anything that happens to mention it
+        assert_eq!(
+            QuizVerdict::of(RunStatus::Error, &zen_error("NotARuntimeErrorKind")),
+            QuizVerdict::CompileError
+        );
+    }
+
+    #[test]
+    fn run_statuses_map_to_verdicts() {
+        let failed = Err(Diagnostic::from_run_failure(
+            "RuntimeError",
+            "crash".to_string(),
+        ));
+        assert_eq!(
+            QuizVerdict::of(RunStatus::RuntimeError, &failed),
+            QuizVerdict::RuntimeError
+        );
+        assert_eq!(
+            QuizVerdict::of(RunStatus::TimeLimitExceeded, &failed),
+            QuizVerdict::TimeLimitExceeded
+        );
+        assert_eq!(
+            QuizVerdict::of(RunStatus::Success, &Ok(true)),
+            QuizVerdict::Accepted
+        );
+        assert_eq!(
+            QuizVerdict::of(RunStatus::Success, &Ok(false)),
+            QuizVerdict::WrongAnswer
+        );
+    }
+
+    #[test]
+    fn scores_weights_per_group_in_order_of_appearance() {
+        let score = score(&[
+            result("samples", 0.0, QuizVerdict::Accepted),
+            result("easy", 1.0, QuizVerdict::Accepted),
+            result("hard", 3.0, QuizVerdict::WrongAnswer),
+            result("easy", 2.0, QuizVerdict::Accepted),
+            result("hard", 4.0, QuizVerdict::Accepted),
+        ]);
+        assert_eq!((score.score, score.max_score), (7.0, 10.0));
+        let groups: Vec<_> = score
+            .groups
+            .iter()
+            .map(|group| {
+                (
+                    group.group.as_str(),
+                    group.score,
+                    group.max_score,
+                    group.passed,
+                )
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                ("samples", 0.0, 0.0, 1),
+                ("easy", 3.0, 3.0, 2),
+                ("hard", 4.0, 7.0, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn the_quiz_verdict_is_the_first_failure() {
+        let score = score(&[
+            result("default", 1.0, QuizVerdict::Accepted),
+            result("default", 1.0, QuizVerdict::TimeLimitExceeded),
+            result("default", 1.0, QuizVerdict::WrongAnswer),
+        ]);
+        assert_eq!(score.verdict, QuizVerdict::TimeLimitExceeded);
+        assert_eq!(score.groups[0].testcases, 3);
+
+        assert_eq!(super::score(&[]).verdict, QuizVerdict::Accepted);
+    }
+}
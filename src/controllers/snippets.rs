@@ -0,0 +1,345 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{self, Path, Query},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use http::StatusCode;
+use lazy_static::lazy_static;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use shuttle_persist::{PersistError, PersistInstance};
+use tower_http::add_extension::AddExtensionLayer;
+
+use crate::controllers::{
+    authentication::{load_or_default, now, user_key, LoggedIn, MaybeLoggedIn, MyState},
+    pagination::{list_page, Page, Pagination},
+};
+
+// Each snippet is its own persist entry, so sharing never rewrites one growing blob
+const SNIPPET_KEY_PREFIX: &str = "snippet_";
+const SNIPPET_ID_LEN: usize = 8;
+const MAX_SNIPPET_BYTES: usize = 64 * 1024;
+// Snippet ids, oldest first, so listing never has to read every stored snippet
+const PUBLIC_INDEX_KEY: &str = "snippets_public";
+const USER_INDEX_PREFIX: &str = "user_snippets_";
+
+lazy_static! {
+    // Saving and deleting snippets rewrite the indexes, which must not interleave
+    static ref INDEX_LOCK: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    /// Listed on `/api/snippets` for everyone.
+    Public,
+    /// Readable by anyone who has the link, but not listed.
+    #[default]
+    Unlisted,
+    /// Readable by its owner only.
+    Private,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Snippet {
+    pub id: String,
+    pub code: String,
+    pub input: String,
+    /// `None` for snippets shared without logging in.
+    pub owner: Option<String>,
+    pub visibility: Visibility,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+}
+
+#[derive(Deserialize)]
+pub struct CreateSnippetRequest {
+    code: String,
+    #[serde(default)]
+    input: String,
+    #[serde(default)]
+    visibility: Visibility,
+}
+
+#[derive(Serialize)]
+pub struct SnippetResponse {
+    status_code: u16,
+    message: String,
+    snippet: Option<Snippet>,
+}
+
+#[derive(Serialize)]
+pub struct SnippetListResponse {
+    status_code: u16,
+    message: String,
+    page: usize,
+    per_page: usize,
+    total: usize,
+    snippets: Vec<Snippet>,
+}
+
+pub fn snippet_routes(state: Arc<MyState>) -> Router {
+    Router::new()
+        .route("/snippets", get(public_snippets).post(create_snippet))
+        .route("/snippets/mine", get(my_snippets))
+        .route("/snippets/:id", get(get_snippet).delete(delete_snippet))
+        .layer(AddExtensionLayer::new(state))
+}
+
+/// Builds the indexes for snippets saved before they existed. This lists every stored key, so it
+/// only runs when there is no public index yet, and it rewrites every index it builds.
+pub fn init(persist: &PersistInstance) {
+    if persist.load::<Vec<String>>(PUBLIC_INDEX_KEY).is_ok() {
+        return;
+    }
+    let mut snippets: Vec<Snippet> = persist
+        .list()
+        .unwrap()
+        .iter()
+        .filter(|key| key.starts_with(SNIPPET_KEY_PREFIX))
+        .filter_map(|key| persist.load::<Snippet>(key).ok())
+        .collect();
+    snippets.sort_by_key(|snippet| snippet.created_at);
+
+    // The public index is saved last, so a restart after a failed run builds everything again
+    let mut indexes: HashMap<String, Vec<String>> = HashMap::new();
+    for snippet in &snippets {
+        for key in index_keys(snippet) {
+            indexes.entry(key).or_default().push(snippet.id.clone());
+        }
+    }
+    let public = indexes.remove(PUBLIC_INDEX_KEY).unwrap_or_default();
+    for (key, ids) in indexes {
+        persist.save::<Vec<String>>(&key, ids).unwrap();
+    }
+    persist
+        .save::<Vec<String>>(PUBLIC_INDEX_KEY, public)
+        .unwrap();
+}
+
+/// Stores a snippet, owned by the caller when a Bearer token is sent.
+pub async fn create_snippet(
+    caller: MaybeLoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Json(req): Json<CreateSnippetRequest>,
+) -> Response {
+    let owner = caller.username();
+    if owner.is_none() && req.visibility == Visibility::Private {
+        return respond(
+            StatusCode::UNAUTHORIZED,
+            "Log in to save private snippets.",
+            None,
+        );
+    }
+    if req.code.len() + req.input.len() > MAX_SNIPPET_BYTES {
+        return respond(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Snippets are limited to {} bytes", MAX_SNIPPET_BYTES),
+            None,
+        );
+    }
+
+    let saved = state
+        .blocking(move |persist| {
+            let id = loop {
+                let id: String = rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(SNIPPET_ID_LEN)
+                    .map(char::from)
+                    .collect();
+                if persist.load::<Snippet>(&snippet_key(&id)).is_err() {
+                    break id;
+                }
+            };
+            let snippet = Snippet {
+                id,
+                code: req.code,
+                input: req.input,
+                owner,
+                visibility: req.visibility,
+                created_at: now(),
+            };
+            persist.save::<&Snippet>(&snippet_key(&snippet.id), &snippet)?;
+            add_to_indexes(persist, &snippet)?;
+            Ok::<_, PersistError>(snippet)
+        })
+        .await;
+
+    match saved {
+        Ok(snippet) => respond(
+            StatusCode::CREATED,
+            "Snippet saved successfully",
+            Some(snippet),
+        ),
+        Err(e) => respond(StatusCode::INTERNAL_SERVER_ERROR, e.to_string(), None),
+    }
+}
+
+/// Private snippets of other users are reported as missing.
+pub async fn get_snippet(
+    caller: MaybeLoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Path(id): Path<String>,
+) -> Response {
+    let viewer = caller.username();
+    match state.blocking(move |persist| load(persist, &id)).await {
+        Some(snippet) if snippet.visibility != Visibility::Private || snippet.owner == viewer => {
+            respond(StatusCode::OK, "Snippet found", Some(snippet))
+        }
+        _ => respond(StatusCode::NOT_FOUND, "Snippet not found.", None),
+    }
+}
+
+pub async fn delete_snippet(
+    LoggedIn(claims): LoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Path(id): Path<String>,
+) -> Response {
+    let username = claims.sub;
+    let deleted = state
+        .blocking(move |persist| match load(persist, &id) {
+            Some(snippet) if snippet.owner.as_ref() == Some(&username) => {
+                persist.remove(&snippet_key(&id))?;
+                remove_from_indexes(persist, &snippet)?;
+                Ok(Some(snippet))
+            }
+            _ => Ok::<_, PersistError>(None),
+        })
+        .await;
+    match deleted {
+        Ok(Some(snippet)) => respond(
+            StatusCode::OK,
+            "Snippet deleted successfully",
+            Some(snippet),
+        ),
+        Ok(None) => respond(StatusCode::NOT_FOUND, "Snippet not found.", None),
+        Err(e) => respond(StatusCode::INTERNAL_SERVER_ERROR, e.to_string(), None),
+    }
+}
+
+pub async fn public_snippets(
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Query(pagination): Query<Pagination>,
+) -> Json<SnippetListResponse> {
+    list(&state, pagination, PUBLIC_INDEX_KEY.to_string()).await
+}
+
+/// All of the caller's snippets, whatever their visibility.
+pub async fn my_snippets(
+    LoggedIn(claims): LoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Query(pagination): Query<Pagination>,
+) -> Json<SnippetListResponse> {
+    list(&state, pagination, user_index_key(&claims.sub)).await
+}
+
+async fn list(
+    state: &MyState,
+    pagination: Pagination,
+    index_key: String,
+) -> Json<SnippetListResponse> {
+    let (page, per_page) = pagination.bounds();
+    let listed = list_page(
+        state,
+        pagination,
+        move |persist| index(persist, &index_key),
+        |persist, id| persist.load::<Snippet>(&snippet_key(&id)),
+    )
+    .await;
+
+    let (status, message, total, snippets) = match listed {
+        Ok(Page { total, items }) => (
+            StatusCode::OK,
+            "Snippets fetched successfully".to_string(),
+            total,
+            items,
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            e.to_string(),
+            0,
+            Vec::new(),
+        ),
+    };
+    Json(SnippetListResponse {
+        status_code: status.into(),
+        message,
+        page,
+        per_page,
+        total,
+        snippets,
+    })
+}
+
+// Ids come from the URL, so anything that could not have been generated is never looked up
+fn load(persist: &PersistInstance, id: &str) -> Option<Snippet> {
+    if id.len() != SNIPPET_ID_LEN || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    persist.load::<Snippet>(&snippet_key(id)).ok()
+}
+
+fn add_to_indexes(persist: &PersistInstance, snippet: &Snippet) -> Result<(), PersistError> {
+    update_indexes(persist, snippet, |ids| ids.push(snippet.id.clone()))
+}
+
+fn remove_from_indexes(persist: &PersistInstance, snippet: &Snippet) -> Result<(), PersistError> {
+    update_indexes(persist, snippet, |ids| ids.retain(|id| *id != snippet.id))
+}
+
+fn update_indexes(
+    persist: &PersistInstance,
+    snippet: &Snippet,
+    change: impl Fn(&mut Vec<String>),
+) -> Result<(), PersistError> {
+    let _guard = INDEX_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    for key in index_keys(snippet) {
+        let mut ids = index(persist, &key)?;
+        change(&mut ids);
+        persist.save::<Vec<String>>(&key, ids)?;
+    }
+    Ok(())
+}
+
+// The owner's index holds every snippet of theirs, the public one only public snippets
+fn index_keys(snippet: &Snippet) -> Vec<String> {
+    let mut keys: Vec<String> = snippet
+        .owner
+        .iter()
+        .map(|owner| user_index_key(owner))
+        .collect();
+    if snippet.visibility == Visibility::Public {
+        keys.push(PUBLIC_INDEX_KEY.to_string());
+    }
+    keys
+}
+
+fn index(persist: &PersistInstance, key: &str) -> Result<Vec<String>, PersistError> {
+    load_or_default::<Vec<String>>(persist, key)
+}
+
+fn snippet_key(id: &str) -> String {
+    format!("{}{}", SNIPPET_KEY_PREFIX, id)
+}
+
+fn user_index_key(username: &str) -> String {
+    user_key(USER_INDEX_PREFIX, username)
+}
+
+fn respond(status: StatusCode, message: impl Into<String>, snippet: Option<Snippet>) -> Response {
+    Json(SnippetResponse {
+        status_code: status.into(),
+        message: message.into(),
+        snippet,
+    })
+    .into_response()
+}
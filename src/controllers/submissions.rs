@@ -0,0 +1,271 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::{self, Path, Query},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use http::StatusCode;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use shuttle_persist::{PersistError, PersistInstance};
+use tower_http::add_extension::AddExtensionLayer;
+
+use crate::{
+    controllers::{
+        authentication::{is_admin, load_or_default, now, user_key, LoggedIn, MyState},
+        compile_code::{RunStatus, TestcaseResult},
+        pagination::{list_page, Page, Pagination},
+    },
+    sandbox::Metrics,
+};
+
+pub const SUBMISSIONS_KEY: &str = "submissions";
+
+// Each submission is its own entry; these keys only hold lists of submission ids, oldest first
+const SUBMISSION_KEY_PREFIX: &str = "submission_";
+const USER_INDEX_PREFIX: &str = "user_submissions_";
+const PROBLEM_INDEX_PREFIX: &str = "problem_submissions_";
+
+lazy_static! {
+    // Runs finish concurrently, so taking an id and appending to the indexes must not interleave
+    static ref LOG_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// The next submission id. Earlier versions kept every submission in here as well; `init` moves
+/// those out into their own entries.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SubmissionLog {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) submissions: Vec<Submission>,
+    pub(crate) next_id: u64,
+}
+
+impl Default for SubmissionLog {
+    fn default() -> Self {
+        Self {
+            submissions: Vec::new(),
+            next_id: 1,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Submission {
+    pub id: u64,
+    pub username: String,
+    /// `None` for `/api/compile` and `/api/quiz` runs that are not tied to a stored problem.
+    pub problem_id: Option<u64>,
+    pub code: String,
+    pub verdicts: Vec<Verdict>,
+    /// Seconds since the Unix epoch.
+    pub submitted_at: u64,
+}
+
+/// How one testcase (or the single run of `/api/compile`) went.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Verdict {
+    pub status: RunStatus,
+    /// `None` when there was nothing to compare against.
+    pub passed: Option<bool>,
+    pub metrics: Metrics,
+}
+
+impl From<&TestcaseResult> for Verdict {
+    fn from(result: &TestcaseResult) -> Self {
+        Verdict {
+            status: result.status,
+            passed: result.passed.as_ref().ok().copied(),
+            metrics: result.metrics,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SubmissionListResponse {
+    status_code: u16,
+    message: String,
+    page: usize,
+    per_page: usize,
+    total: usize,
+    submissions: Vec<Submission>,
+}
+
+#[derive(Serialize)]
+pub struct SubmissionResponse {
+    status_code: u16,
+    message: String,
+    submission: Option<Submission>,
+}
+
+pub fn submission_routes(state: Arc<MyState>) -> Router {
+    Router::new()
+        .route("/submissions", get(my_submissions))
+        .route("/submissions/:id", get(get_submission))
+        .route("/problems/:id/submissions", get(problem_submissions))
+        .layer(AddExtensionLayer::new(state))
+}
+
+/// Creates the submission log on first start and moves submissions stored by earlier versions
+/// into their own entries.
+pub fn init(persist: &PersistInstance) {
+    let mut log = load_or_default::<SubmissionLog>(persist, SUBMISSIONS_KEY).unwrap();
+    for submission in std::mem::take(&mut log.submissions) {
+        store(persist, &submission).unwrap();
+    }
+    persist.save::<SubmissionLog>(SUBMISSIONS_KEY, log).unwrap();
+}
+
+/// Records a submission. A failure to save is reported but never fails the run.
+pub(crate) async fn record(
+    state: &MyState,
+    username: String,
+    problem_id: Option<u64>,
+    code: String,
+    verdicts: Vec<Verdict>,
+) {
+    let saved = state
+        .blocking(move |persist| {
+            let _guard = LOG_LOCK
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let mut log = persist.load::<SubmissionLog>(SUBMISSIONS_KEY)?;
+            let submission = Submission {
+                id: log.next_id,
+                username,
+                problem_id,
+                code,
+                verdicts,
+                submitted_at: now(),
+            };
+            log.next_id += 1;
+            persist.save::<SubmissionLog>(SUBMISSIONS_KEY, log)?;
+            store(persist, &submission)
+        })
+        .await;
+    if let Err(err) = saved {
+        eprintln!("Failed to record submission: {:?}", err);
+    }
+}
+
+/// The caller's own submissions, newest first.
+pub async fn my_submissions(
+    LoggedIn(claims): LoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Query(pagination): Query<Pagination>,
+) -> Response {
+    let user_index = user_index_key(&claims.sub);
+    list(&state, pagination, move |persist| {
+        index(persist, &user_index)
+    })
+    .await
+}
+
+/// Every attempt at a problem for admins; only their own for everyone else.
+pub async fn problem_submissions(
+    LoggedIn(claims): LoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Path(problem_id): Path<u64>,
+    Query(pagination): Query<Pagination>,
+) -> Response {
+    let admin = is_admin(&state.secrets, &claims);
+    let user_index = user_index_key(&claims.sub);
+    list(&state, pagination, move |persist| {
+        let ids = index(persist, &problem_index_key(problem_id))?;
+        if admin {
+            return Ok(ids);
+        }
+        let own = index(persist, &user_index)?;
+        Ok(ids.into_iter().filter(|id| own.contains(id)).collect())
+    })
+    .await
+}
+
+pub async fn get_submission(
+    LoggedIn(claims): LoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Path(id): Path<u64>,
+) -> Response {
+    let submission = state
+        .blocking(move |persist| persist.load::<Submission>(&submission_key(id)).ok())
+        .await;
+    // Someone else's submission is reported as missing rather than forbidden
+    let submission = submission.filter(|submission| {
+        submission.username == claims.sub || is_admin(&state.secrets, &claims)
+    });
+    match submission {
+        Some(submission) => Json(SubmissionResponse {
+            status_code: StatusCode::OK.into(),
+            message: "Submission found".to_string(),
+            submission: Some(submission),
+        }),
+        None => Json(SubmissionResponse {
+            status_code: StatusCode::NOT_FOUND.into(),
+            message: "Submission not found.".to_string(),
+            submission: None,
+        }),
+    }
+    .into_response()
+}
+
+async fn list(
+    state: &MyState,
+    pagination: Pagination,
+    ids: impl FnOnce(&PersistInstance) -> Result<Vec<u64>, PersistError> + Send + 'static,
+) -> Response {
+    let (page, per_page) = pagination.bounds();
+    let listed = list_page(state, pagination, ids, |persist, id| {
+        persist.load::<Submission>(&submission_key(id))
+    })
+    .await;
+    let response = match listed {
+        Ok(Page { total, items }) => SubmissionListResponse {
+            status_code: StatusCode::OK.into(),
+            message: "Submissions fetched successfully".to_string(),
+            page,
+            per_page,
+            total,
+            submissions: items,
+        },
+        Err(e) => SubmissionListResponse {
+            status_code: StatusCode::INTERNAL_SERVER_ERROR.into(),
+            message: e.to_string(),
+            page,
+            per_page,
+            total: 0,
+            submissions: Vec::new(),
+        },
+    };
+    Json(response).into_response()
+}
+
+// Saves the entry and adds it to its user's and problem's indexes
+fn store(persist: &PersistInstance, submission: &Submission) -> Result<(), PersistError> {
+    persist.save::<&Submission>(&submission_key(submission.id), submission)?;
+    let mut keys = vec![user_index_key(&submission.username)];
+    keys.extend(submission.problem_id.map(problem_index_key));
+    for key in keys {
+        let mut ids = index(persist, &key)?;
+        ids.push(submission.id);
+        persist.save::<Vec<u64>>(&key, ids)?;
+    }
+    Ok(())
+}
+
+// A missing index is an empty one
+fn index(persist: &PersistInstance, key: &str) -> Result<Vec<u64>, PersistError> {
+    load_or_default::<Vec<u64>>(persist, key)
+}
+
+fn submission_key(id: u64) -> String {
+    format!("{}{}", SUBMISSION_KEY_PREFIX, id)
+}
+
+fn problem_index_key(problem_id: u64) -> String {
+    format!("{}{}", PROBLEM_INDEX_PREFIX, problem_id)
+}
+
+fn user_index_key(username: &str) -> String {
+    user_key(USER_INDEX_PREFIX, username)
+}
@@ -0,0 +1,432 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{self, Path},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use http::StatusCode;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use shuttle_persist::{PersistError, PersistInstance};
+use tower_http::add_extension::AddExtensionLayer;
+
+use crate::{
+    compilers::Compiler,
+    controllers::{
+        authentication::{load_or_default, now, user_key, LoggedIn, MyState},
+        compile_code::{run_code, CodeCompileRequest},
+    },
+    run_cache::RunCache,
+    run_config::RunConfig,
+    run_queue::RunQueue,
+};
+
+// One persist entry per user, so saving a file only rewrites that user's projects
+const WORKSPACE_KEY_PREFIX: &str = "workspace_";
+const MAX_PROJECTS: usize = 50;
+const MAX_NAME_LEN: usize = 64;
+const MAX_PROJECT_BYTES: usize = 256 * 1024;
+
+lazy_static! {
+    // One lock per user, so two saves of the same workspace never overwrite each other while
+    // different users save in parallel
+    static ref WORKSPACE_LOCKS: Mutex<HashMap<String, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Workspace {
+    pub(crate) projects: Vec<Project>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Project {
+    pub name: String,
+    pub files: Vec<ProjectFile>,
+    /// Seconds since the Unix epoch.
+    pub updated_at: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ProjectFile {
+    pub name: String,
+    pub content: String,
+}
+
+#[derive(Serialize)]
+pub struct ProjectSummary {
+    name: String,
+    files: Vec<String>,
+    updated_at: u64,
+}
+
+#[derive(Deserialize)]
+pub struct CreateProjectRequest {
+    name: String,
+    #[serde(default)]
+    files: Vec<ProjectFile>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateProjectRequest {
+    files: Vec<ProjectFile>,
+}
+
+#[derive(Deserialize)]
+pub struct SaveFileRequest {
+    content: String,
+}
+
+#[derive(Deserialize)]
+pub struct RunProjectRequest {
+    entry: String,
+    #[serde(default)]
+    input: String,
+    #[serde(default)]
+    no_cache: bool,
+}
+
+#[derive(Serialize)]
+pub struct ProjectResponse {
+    status_code: u16,
+    message: String,
+    project: Option<Project>,
+}
+
+#[derive(Serialize)]
+pub struct ProjectListResponse {
+    status_code: u16,
+    message: String,
+    projects: Vec<ProjectSummary>,
+}
+
+pub fn workspace_routes(state: Arc<MyState>) -> Router {
+    Router::new()
+        .route(
+            "/workspace/projects",
+            get(list_projects).post(create_project),
+        )
+        .route(
+            "/workspace/projects/:project",
+            get(get_project).put(update_project).delete(delete_project),
+        )
+        .route(
+            "/workspace/projects/:project/files/:file",
+            put(save_file).delete(delete_file),
+        )
+        .route("/workspace/projects/:project/run", post(run_project))
+        .layer(AddExtensionLayer::new(state))
+}
+
+pub async fn list_projects(
+    LoggedIn(claims): LoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+) -> Response {
+    let username = claims.sub;
+    let response = match state
+        .blocking(move |persist| load(persist, &username))
+        .await
+    {
+        Ok(workspace) => ProjectListResponse {
+            status_code: StatusCode::OK.into(),
+            message: "Projects fetched successfully".to_string(),
+            projects: workspace
+                .projects
+                .into_iter()
+                .map(|project| ProjectSummary {
+                    name: project.name,
+                    files: project.files.into_iter().map(|file| file.name).collect(),
+                    updated_at: project.updated_at,
+                })
+                .collect(),
+        },
+        Err(e) => ProjectListResponse {
+            status_code: StatusCode::INTERNAL_SERVER_ERROR.into(),
+            message: e.to_string(),
+            projects: Vec::new(),
+        },
+    };
+    Json(response).into_response()
+}
+
+pub async fn get_project(
+    LoggedIn(claims): LoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Path(name): Path<String>,
+) -> Response {
+    let username = claims.sub;
+    match state
+        .blocking(move |persist| load(persist, &username))
+        .await
+    {
+        Ok(workspace) => match workspace.projects.into_iter().find(|p| p.name == name) {
+            Some(project) => respond(StatusCode::OK, "Project found", Some(project)),
+            None => respond(StatusCode::NOT_FOUND, "Project not found.", None),
+        },
+        Err(e) => respond(StatusCode::INTERNAL_SERVER_ERROR, e.to_string(), None),
+    }
+}
+
+pub async fn create_project(
+    LoggedIn(claims): LoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Json(req): Json<CreateProjectRequest>,
+) -> Response {
+    let username = claims.sub;
+    modify(&state, username, StatusCode::CREATED, move |workspace| {
+        if workspace.projects.iter().any(|p| p.name == req.name) {
+            return Err((
+                StatusCode::CONFLICT,
+                "A project with this name already exists",
+            ));
+        }
+        if workspace.projects.len() >= MAX_PROJECTS {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Too many projects, delete one first",
+            ));
+        }
+        let project = Project {
+            name: req.name,
+            files: req.files,
+            updated_at: now(),
+        };
+        validate(&project)?;
+        workspace.projects.push(project.clone());
+        Ok(project)
+    })
+    .await
+}
+
+/// Replaces all files of a project.
+pub async fn update_project(
+    LoggedIn(claims): LoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Path(name): Path<String>,
+    Json(req): Json<UpdateProjectRequest>,
+) -> Response {
+    let username = claims.sub;
+    modify(&state, username, StatusCode::OK, move |workspace| {
+        let project = find(workspace, &name)?;
+        let updated = Project {
+            name: project.name.clone(),
+            files: req.files,
+            updated_at: now(),
+        };
+        validate(&updated)?;
+        *project = updated.clone();
+        Ok(updated)
+    })
+    .await
+}
+
+pub async fn delete_project(
+    LoggedIn(claims): LoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Path(name): Path<String>,
+) -> Response {
+    let username = claims.sub;
+    modify(
+        &state,
+        username,
+        StatusCode::OK,
+        move |workspace| match workspace.projects.iter().position(|p| p.name == name) {
+            Some(index) => Ok(workspace.projects.remove(index)),
+            None => Err((StatusCode::NOT_FOUND, "Project not found.")),
+        },
+    )
+    .await
+}
+
+/// Creates or overwrites one file of a project.
+pub async fn save_file(
+    LoggedIn(claims): LoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Path((name, file)): Path<(String, String)>,
+    Json(req): Json<SaveFileRequest>,
+) -> Response {
+    let username = claims.sub;
+    modify(&state, username, StatusCode::OK, move |workspace| {
+        let project = find(workspace, &name)?;
+        let mut updated = project.clone();
+        match updated.files.iter_mut().find(|f| f.name == file) {
+            Some(existing) => existing.content = req.content,
+            None => updated.files.push(ProjectFile {
+                name: file,
+                content: req.content,
+            }),
+        }
+        updated.updated_at = now();
+        validate(&updated)?;
+        *project = updated.clone();
+        Ok(updated)
+    })
+    .await
+}
+
+pub async fn delete_file(
+    LoggedIn(claims): LoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    Path((name, file)): Path<(String, String)>,
+) -> Response {
+    let username = claims.sub;
+    modify(&state, username, StatusCode::OK, move |workspace| {
+        let project = find(workspace, &name)?;
+        match project.files.iter().position(|f| f.name == file) {
+            Some(index) => {
+                project.files.remove(index);
+                project.updated_at = now();
+                Ok(project.clone())
+            }
+            None => Err((StatusCode::NOT_FOUND, "File not found.")),
+        }
+    })
+    .await
+}
+
+/// Runs the project's `entry` file like `/api/compile` would.
+///
+/// `zen::run_program` takes a single source string, so the other files of the project are not
+/// visible to the program.
+pub async fn run_project(
+    LoggedIn(claims): LoggedIn,
+    extract::Extension(state): extract::Extension<Arc<MyState>>,
+    extract::Extension(config): extract::Extension<Arc<RunConfig>>,
+    extract::Extension(queue): extract::Extension<Arc<RunQueue>>,
+    extract::Extension(cache): extract::Extension<Arc<RunCache>>,
+    Path(name): Path<String>,
+    Json(req): Json<RunProjectRequest>,
+) -> Response {
+    let username = claims.sub;
+    let key = workspace_key(&username);
+    let workspace = match state
+        .blocking(move |persist| load_or_default::<Workspace>(persist, &key))
+        .await
+    {
+        Ok(workspace) => workspace,
+        Err(e) => return respond(StatusCode::INTERNAL_SERVER_ERROR, e.to_string(), None),
+    };
+    let code = match workspace
+        .projects
+        .into_iter()
+        .find(|p| p.name == name)
+        .map(|project| project.files.into_iter().find(|f| f.name == req.entry))
+    {
+        Some(Some(file)) => file.content,
+        Some(None) => return respond(StatusCode::NOT_FOUND, "Entry file not found.", None),
+        None => return respond(StatusCode::NOT_FOUND, "Project not found.", None),
+    };
+
+    let request = CodeCompileRequest {
+        code,
+        input: req.input,
+        no_cache: req.no_cache,
+        debug: false,
+        version: None,
+    };
+    match run_code(
+        &state,
+        &cache,
+        &Compiler::BUNDLED,
+        Some(username),
+        request,
+        &config,
+        &queue,
+    )
+    .await
+    {
+        Ok(response) => Json(response).into_response(),
+        Err(full) => full.into_response(),
+    }
+}
+
+// Loads the workspace, lets `change` edit it and saves it again, answering with the project
+// `change` returns
+async fn modify(
+    state: &MyState,
+    username: String,
+    status: StatusCode,
+    change: impl FnOnce(&mut Workspace) -> Result<Project, (StatusCode, &'static str)> + Send + 'static,
+) -> Response {
+    let lock = user_lock(&username);
+    let modified = state
+        .blocking(move |persist| {
+            let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let mut workspace = load(persist, &username)?;
+            let project = match change(&mut workspace) {
+                Ok(project) => project,
+                Err(rejected) => return Ok(Err(rejected)),
+            };
+            persist.save::<Workspace>(&workspace_key(&username), workspace)?;
+            Ok::<_, PersistError>(Ok(project))
+        })
+        .await;
+    match modified {
+        Ok(Ok(project)) => respond(status, "Workspace saved successfully", Some(project)),
+        Ok(Err((status, message))) => respond(status, message, None),
+        Err(e) => respond(StatusCode::INTERNAL_SERVER_ERROR, e.to_string(), None),
+    }
+}
+
+// Locks nobody holds any more are dropped, so the map only grows with concurrent users
+fn user_lock(username: &str) -> Arc<Mutex<()>> {
+    let mut locks = WORKSPACE_LOCKS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+    locks.entry(username.to_string()).or_default().clone()
+}
+
+// A user without a saved workspace has an empty one
+fn load(persist: &PersistInstance, username: &str) -> Result<Workspace, PersistError> {
+    load_or_default::<Workspace>(persist, &workspace_key(username))
+}
+
+fn find<'a>(
+    workspace: &'a mut Workspace,
+    name: &str,
+) -> Result<&'a mut Project, (StatusCode, &'static str)> {
+    workspace
+        .projects
+        .iter_mut()
+        .find(|p| p.name == name)
+        .ok_or((StatusCode::NOT_FOUND, "Project not found."))
+}
+
+fn validate(project: &Project) -> Result<(), (StatusCode, &'static str)> {
+    let bad_name = |name: &str| name.trim().is_empty() || name.len() > MAX_NAME_LEN;
+    if bad_name(&project.name) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid project name"));
+    }
+    if project.files.iter().any(|file| bad_name(&file.name)) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid file name"));
+    }
+    let mut names: Vec<&str> = project.files.iter().map(|f| f.name.as_str()).collect();
+    names.sort_unstable();
+    names.dedup();
+    if names.len() != project.files.len() {
+        return Err((StatusCode::BAD_REQUEST, "File names must be unique"));
+    }
+    let bytes: usize = project.files.iter().map(|f| f.content.len()).sum();
+    if bytes > MAX_PROJECT_BYTES {
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, "Project is too large"));
+    }
+    Ok(())
+}
+
+fn workspace_key(username: &str) -> String {
+    user_key(WORKSPACE_KEY_PREFIX, username)
+}
+
+fn respond(status: StatusCode, message: impl Into<String>, project: Option<Project>) -> Response {
+    Json(ProjectResponse {
+        status_code: status.into(),
+        message: message.into(),
+        project,
+    })
+    .into_response()
+}
@@ -1,19 +1,42 @@
+use std::sync::Arc;
+
 use axum::{
+    extract::Extension,
     routing::{get, post},
     Router,
 };
-use controllers::authentication::{auth_routes, UserData};
+use compilers::Compilers;
+use controllers::{
+    authentication::{auth_routes, MyState, UserData},
+    problems::{self, problem_routes},
+    snippets::{self, snippet_routes},
+    submissions::{self, submission_routes},
+    workspaces::workspace_routes,
+};
+use run_cache::RunCache;
+use run_config::RunConfig;
+use run_queue::RunQueue;
 use shuttle_persist::PersistInstance;
 use shuttle_runtime::SecretStore;
-use tower_http::cors::CorsLayer;
+use tower_http::{add_extension::AddExtensionLayer, cors::CorsLayer};
 
+mod compilers;
 mod controllers;
+mod run_cache;
+mod run_config;
+mod run_queue;
+mod sandbox;
 mod smtp_config;
 
-async fn api_health() -> &'static str {
-    "Zen is High Dear!\nCompiler Version: v0.2.6"
-        .to_string()
-        .leak()
+/// The zen revision this server is built with, as pinned by Cargo.lock (see `build.rs`).
+pub const COMPILER_VERSION: &str = env!("ZEN_COMPILER_VERSION");
+
+async fn api_health(Extension(compilers): Extension<Arc<Compilers>>) -> String {
+    format!(
+        "Zen is High Dear!\nCompiler Version: {}\nAvailable Versions: {}",
+        COMPILER_VERSION,
+        compilers.versions().join(", ")
+    )
 }
 
 #[shuttle_runtime::main]
@@ -29,13 +52,42 @@ async fn axum(
     if persist.load::<UserData>("data").is_err() {
         persist.save::<UserData>("data", UserData::new()).unwrap();
     }
+    problems::init(&persist);
+    submissions::init(&persist);
+    snippets::init(&persist);
     let cors = CorsLayer::permissive();
+    let run_config = Arc::new(RunConfig::init(&secret_store));
+    let run_queue = Arc::new(RunQueue::new(
+        run_config.workers,
+        run_config.max_queue_depth,
+    ));
+    let run_cache = Arc::new(RunCache::new(run_config.cache_bytes));
+    let compilers = Arc::new(Compilers::init(&secret_store));
+    let state = Arc::new(MyState::new(persist, secret_store));
 
     let api_router = Router::new()
         .route("/health", get(api_health))
         .route("/compile", post(controllers::compile_code::compile_code))
+        .route(
+            "/compile/batch",
+            post(controllers::compile_code::compile_batch),
+        )
         .route("/quiz", post(controllers::compile_code::take_quiz))
-        .merge(auth_routes(persist, secret_store))
+        .route("/versions", get(controllers::compile_code::versions))
+        .route("/queue", get(controllers::compile_code::queue_status))
+        .route("/cache", get(controllers::compile_code::cache_stats))
+        .route("/run/ws", get(controllers::run_ws::run_ws))
+        .route("/run/session", get(controllers::run_session::run_session))
+        .merge(problem_routes(state.clone()))
+        .merge(submission_routes(state.clone()))
+        .merge(snippet_routes(state.clone()))
+        .merge(workspace_routes(state.clone()))
+        .layer(AddExtensionLayer::new(state.clone()))
+        .layer(AddExtensionLayer::new(run_config))
+        .layer(AddExtensionLayer::new(run_queue))
+        .layer(AddExtensionLayer::new(run_cache))
+        .layer(AddExtensionLayer::new(compilers))
+        .merge(auth_routes(state))
         .layer(cors.clone());
 
     let router = Router::new().nest("/api", api_router).layer(cors);
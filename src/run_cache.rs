@@ -0,0 +1,314 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use serde::Serialize;
+
+use crate::{
+    compilers::Compiler,
+    controllers::compile_code::{Execution, RunStatus},
+    run_config::RunConfig,
+};
+
+// Rough per-entry bookkeeping cost on top of the stored strings
+const ENTRY_OVERHEAD_BYTES: usize = 256;
+
+/// Results of earlier runs, so identical programs do not have to run again.
+///
+/// Entries are found by a hash of the compiler version, the code, the input and the limits the
+/// run had; the code and input are compared as well, so a hash collision is only a miss. The
+/// least recently used entries are dropped once `capacity_bytes` is exceeded.
+pub struct RunCache {
+    capacity_bytes: usize,
+    lru: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub capacity_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<u64, Entry>,
+    // Last use -> hash, oldest first
+    order: BTreeMap<u64, u64>,
+    tick: u64,
+    bytes: usize,
+}
+
+struct Entry {
+    code: String,
+    input: String,
+    execution: Execution,
+    last_used: u64,
+    size: usize,
+}
+
+impl RunCache {
+    /// A cache of `capacity_bytes`; `0` disables caching.
+    pub fn new(capacity_bytes: usize) -> Self {
+        Self {
+            capacity_bytes,
+            lru: Mutex::new(Lru::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(
+        &self,
+        compiler: &Compiler,
+        code: &str,
+        input: &str,
+        config: &RunConfig,
+    ) -> Option<Execution> {
+        if self.capacity_bytes == 0 {
+            return None;
+        }
+        let hash = key(compiler, code, input, config);
+        let mut lru = self.lru.lock().unwrap_or_else(|e| e.into_inner());
+        let found = lru.touch(hash, code, input);
+        match &found {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        found
+    }
+
+    /// Stores a finished run. Only outcomes that depend on nothing but the code and input are
+    /// kept: output and interpreter errors, not crashes or timeouts.
+    pub fn insert(
+        &self,
+        compiler: &Compiler,
+        code: &str,
+        input: &str,
+        config: &RunConfig,
+        execution: &Execution,
+    ) {
+        if !matches!(execution.status, RunStatus::Success | RunStatus::Error) {
+            return;
+        }
+        let size = ENTRY_OVERHEAD_BYTES
+            + code.len()
+            + input.len()
+            + match &execution.output {
+                Ok(output) => output.len(),
+                Err(diagnostic) => diagnostic.message.len(),
+            };
+        if size > self.capacity_bytes {
+            return;
+        }
+
+        let hash = key(compiler, code, input, config);
+        let mut lru = self.lru.lock().unwrap_or_else(|e| e.into_inner());
+        lru.remove(hash);
+        while lru.bytes + size > self.capacity_bytes {
+            match lru.order.first_key_value() {
+                Some((_, &oldest)) => lru.remove(oldest),
+                None => break,
+            }
+        }
+        lru.tick += 1;
+        let last_used = lru.tick;
+        lru.order.insert(last_used, hash);
+        lru.bytes += size;
+        lru.entries.insert(
+            hash,
+            Entry {
+                code: code.to_string(),
+                input: input.to_string(),
+                execution: execution.clone(),
+                last_used,
+                size,
+            },
+        );
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let lru = self.lru.lock().unwrap_or_else(|e| e.into_inner());
+        CacheStats {
+            entries: lru.entries.len(),
+            bytes: lru.bytes,
+            capacity_bytes: self.capacity_bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Lru {
+    fn touch(&mut self, hash: u64, code: &str, input: &str) -> Option<Execution> {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self
+            .entries
+            .get_mut(&hash)
+            .filter(|entry| entry.code == code && entry.input == input)?;
+        self.order.remove(&entry.last_used);
+        self.order.insert(tick, hash);
+        entry.last_used = tick;
+        Some(entry.execution.clone())
+    }
+
+    fn remove(&mut self, hash: u64) {
+        if let Some(entry) = self.entries.remove(&hash) {
+            self.order.remove(&entry.last_used);
+            self.bytes -= entry.size;
+        }
+    }
+}
+
+fn key(compiler: &Compiler, code: &str, input: &str, config: &RunConfig) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    compiler.version.hash(&mut hasher);
+    code.hash(&mut hasher);
+    input.hash(&mut hasher);
+    // A run that hit a limit under one configuration may not under another
+    config.time_limit.hash(&mut hasher);
+    config.cpu_limit_secs.hash(&mut hasher);
+    config.memory_limit_bytes.hash(&mut hasher);
+    config.max_output_bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, time::Duration};
+
+    use super::*;
+    use crate::sandbox::Metrics;
+
+    // Every entry below takes exactly this much of the capacity
+    const ENTRY_BYTES: usize = ENTRY_OVERHEAD_BYTES + 44;
+
+    fn config() -> RunConfig {
+        RunConfig {
+            time_limit: Duration::from_secs(5),
+            cpu_limit_secs: 5,
+            memory_limit_bytes: 512 * 1024 * 1024,
+            max_output_bytes: 1024,
+            workers: 1,
+            max_queue_depth: 1,
+            session_idle_timeout: Duration::from_secs(300),
+            max_session_runs: 50,
+            cache_bytes: 0,
+            max_batch_size: 1,
+        }
+    }
+
+    fn execution(status: RunStatus) -> Execution {
+        Execution {
+            status,
+            output: Ok(String::new()),
+            dropped_bytes: 0,
+            metrics: Metrics {
+                wall_time_ms: 0,
+                cpu_time_ms: None,
+                peak_memory_kb: None,
+            },
+            trace: None,
+        }
+    }
+
+    // 44 bytes of code, so each entry is `ENTRY_BYTES`
+    fn code(name: char) -> String {
+        name.to_string().repeat(44)
+    }
+
+    fn insert(cache: &RunCache, name: char) {
+        let success = execution(RunStatus::Success);
+        cache.insert(&Compiler::BUNDLED, &code(name), "", &config(), &success);
+    }
+
+    fn cached(cache: &RunCache, name: char) -> bool {
+        cache
+            .get(&Compiler::BUNDLED, &code(name), "", &config())
+            .is_some()
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let cache = RunCache::new(3 * ENTRY_BYTES);
+        insert(&cache, 'a');
+        insert(&cache, 'b');
+        insert(&cache, 'c');
+        insert(&cache, 'd');
+        assert!(!cached(&cache, 'a'));
+        assert!(cached(&cache, 'b') && cached(&cache, 'c') && cached(&cache, 'd'));
+        assert_eq!(cache.stats().bytes, 3 * ENTRY_BYTES);
+    }
+
+    #[test]
+    fn a_hit_makes_an_entry_recent_again() {
+        let cache = RunCache::new(3 * ENTRY_BYTES);
+        insert(&cache, 'a');
+        insert(&cache, 'b');
+        insert(&cache, 'c');
+        assert!(cached(&cache, 'a'));
+        insert(&cache, 'd');
+        assert!(cached(&cache, 'a'));
+        assert!(!cached(&cache, 'b'));
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (3, 2, 1));
+    }
+
+    #[test]
+    fn inserting_again_replaces_the_entry() {
+        let cache = RunCache::new(3 * ENTRY_BYTES);
+        insert(&cache, 'a');
+        insert(&cache, 'a');
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.bytes), (1, ENTRY_BYTES));
+    }
+
+    #[test]
+    fn keeps_only_reproducible_outcomes() {
+        let cache = RunCache::new(3 * ENTRY_BYTES);
+        for status in [RunStatus::RuntimeError, RunStatus::TimeLimitExceeded] {
+            cache.insert(
+                &Compiler::BUNDLED,
+                &code('a'),
+                "",
+                &config(),
+                &execution(status),
+            );
+        }
+        assert!(!cached(&cache, 'a'));
+    }
+
+    #[test]
+    fn runs_on_another_compiler_or_with_other_limits_miss() {
+        let cache = RunCache::new(3 * ENTRY_BYTES);
+        insert(&cache, 'a');
+        let other = Compiler {
+            version: Cow::Borrowed("v0.0.1"),
+            runner: None,
+        };
+        assert!(cache.get(&other, &code('a'), "", &config()).is_none());
+        let mut slower = config();
+        slower.time_limit = Duration::from_secs(10);
+        assert!(cache
+            .get(&Compiler::BUNDLED, &code('a'), "", &slower)
+            .is_none());
+    }
+
+    #[test]
+    fn a_zero_capacity_disables_caching() {
+        let cache = RunCache::new(0);
+        insert(&cache, 'a');
+        assert!(!cached(&cache, 'a'));
+        assert_eq!(cache.stats().misses, 0);
+    }
+}
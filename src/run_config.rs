@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use shuttle_runtime::SecretStore;
+
+const DEFAULT_TIME_LIMIT_MS: u64 = 5000;
+const DEFAULT_CPU_LIMIT_SECS: u64 = 5;
+const DEFAULT_MEMORY_LIMIT_MB: u64 = 512;
+const DEFAULT_OUTPUT_LIMIT_BYTES: usize = 1024 * 1024;
+const DEFAULT_QUEUE_DEPTH: usize = 64;
+const DEFAULT_SESSION_IDLE_SECS: u64 = 300;
+const DEFAULT_SESSION_RUNS: usize = 50;
+const DEFAULT_CACHE_MB: usize = 32;
+const DEFAULT_BATCH_SIZE: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct RunConfig {
+    pub time_limit: Duration,
+    pub cpu_limit_secs: u64,
+    pub memory_limit_bytes: u64,
+    pub max_output_bytes: usize,
+    pub workers: usize,
+    pub max_queue_depth: usize,
+    pub session_idle_timeout: Duration,
+    pub max_session_runs: usize,
+    pub cache_bytes: usize,
+    pub max_batch_size: usize,
+}
+
+impl RunConfig {
+    pub fn init(secrets: &SecretStore) -> RunConfig {
+        let time_limit_ms = get_or(secrets, "RUN_TIME_LIMIT_MS", DEFAULT_TIME_LIMIT_MS);
+        let cpu_limit_secs = get_or(secrets, "RUN_CPU_LIMIT_SECS", DEFAULT_CPU_LIMIT_SECS);
+        let memory_limit_mb = get_or(secrets, "RUN_MEMORY_LIMIT_MB", DEFAULT_MEMORY_LIMIT_MB);
+        let max_output_bytes = get_or(
+            secrets,
+            "RUN_OUTPUT_LIMIT_BYTES",
+            DEFAULT_OUTPUT_LIMIT_BYTES,
+        );
+        let default_workers = std::thread::available_parallelism().map_or(2, |n| n.get());
+        let workers = get_or(secrets, "RUN_WORKERS", default_workers).max(1);
+        let max_queue_depth = get_or(secrets, "RUN_QUEUE_DEPTH", DEFAULT_QUEUE_DEPTH);
+        let session_idle_secs = get_or(secrets, "RUN_SESSION_IDLE_SECS", DEFAULT_SESSION_IDLE_SECS);
+        let max_session_runs = get_or(secrets, "RUN_SESSION_MAX_RUNS", DEFAULT_SESSION_RUNS).max(1);
+        let cache_mb = get_or(secrets, "RUN_CACHE_MB", DEFAULT_CACHE_MB);
+        let max_batch_size = get_or(secrets, "RUN_BATCH_LIMIT", DEFAULT_BATCH_SIZE);
+
+        RunConfig {
+            time_limit: Duration::from_millis(time_limit_ms),
+            cpu_limit_secs,
+            memory_limit_bytes: memory_limit_mb * 1024 * 1024,
+            max_output_bytes,
+            workers,
+            max_queue_depth,
+            session_idle_timeout: Duration::from_secs(session_idle_secs),
+            max_session_runs,
+            cache_bytes: cache_mb * 1024 * 1024,
+            max_batch_size,
+        }
+    }
+}
+
+// Limits are optional secrets, so fall back to a sane default when they are not set
+fn get_or<T: std::str::FromStr>(secrets: &SecretStore, key: &str, default: T) -> T {
+    match secrets.get(key) {
+        Some(value) => value
+            .trim()
+            .parse::<T>()
+            .unwrap_or_else(|_| panic!("{} must be a number", key)),
+        None => default,
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+// Used for wait estimates until the first run has finished
+const INITIAL_RUN_ESTIMATE_MS: u64 = 1000;
+
+/// Admission control for program runs: at most `workers` run at once and at most `max_depth`
+/// more wait in line; anyone beyond that is turned away with a retry hint.
+pub struct RunQueue {
+    workers: Semaphore,
+    worker_count: usize,
+    max_depth: usize,
+    waiting: AtomicUsize,
+    avg_run_ms: AtomicU64,
+}
+
+/// Held for the duration of a run; frees the worker slot when dropped.
+pub struct Ticket<'a> {
+    _permit: SemaphorePermit<'a>,
+    queue: &'a RunQueue,
+    position: usize,
+    waited: Duration,
+    started: Instant,
+}
+
+/// A place in line, taken before waiting so its position can be reported while it waits.
+pub struct Place<'a> {
+    queue: &'a RunQueue,
+    // Set when a worker was free on arrival
+    permit: Option<SemaphorePermit<'a>>,
+    _in_line: Option<InLine<'a>>,
+    position: usize,
+    arrived: Instant,
+}
+
+/// Extra worker slots lent to a job that can use more than one; returned when dropped.
+pub struct BorrowedWorkers<'a> {
+    permits: Vec<SemaphorePermit<'a>>,
+}
+
+#[derive(Debug)]
+pub struct QueueFull {
+    pub retry_after_secs: u64,
+}
+
+/// Where a request stood in line when it arrived (0 means a worker was free) and how long it
+/// waited for one.
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct QueueInfo {
+    pub position: usize,
+    pub waited_ms: u64,
+}
+
+#[derive(Serialize)]
+pub struct QueueStatus {
+    pub workers: usize,
+    pub running: usize,
+    pub waiting: usize,
+    pub max_depth: usize,
+    pub estimated_wait_secs: u64,
+}
+
+impl RunQueue {
+    pub fn new(worker_count: usize, max_depth: usize) -> Self {
+        Self {
+            workers: Semaphore::new(worker_count),
+            worker_count,
+            max_depth,
+            waiting: AtomicUsize::new(0),
+            avg_run_ms: AtomicU64::new(INITIAL_RUN_ESTIMATE_MS),
+        }
+    }
+
+    /// Waits for a free worker, or fails straight away when the line is already full.
+    pub async fn enter(&self) -> Result<Ticket<'_>, QueueFull> {
+        Ok(self.join()?.ready().await)
+    }
+
+    /// Takes a place in line without waiting, or fails when the line is already full.
+    pub fn join(&self) -> Result<Place<'_>, QueueFull> {
+        let arrived = Instant::now();
+        if let Ok(permit) = self.workers.try_acquire() {
+            return Ok(Place {
+                queue: self,
+                permit: Some(permit),
+                _in_line: None,
+                position: 0,
+                arrived,
+            });
+        }
+
+        let ahead = self.waiting.fetch_add(1, Ordering::SeqCst);
+        let in_line = InLine(&self.waiting);
+        if ahead >= self.max_depth {
+            return Err(QueueFull {
+                retry_after_secs: self.estimated_wait_secs(),
+            });
+        }
+        Ok(Place {
+            queue: self,
+            permit: None,
+            _in_line: Some(in_line),
+            position: ahead + 1,
+            arrived,
+        })
+    }
+
+    /// Takes up to `max` workers that are idle right now. Never waits, and lends nothing while
+    /// anyone is in line, so borrowing cannot hold up other requests.
+    pub fn borrow_idle(&self, max: usize) -> BorrowedWorkers<'_> {
+        let permits = if self.waiting.load(Ordering::SeqCst) > 0 {
+            Vec::new()
+        } else {
+            std::iter::from_fn(|| self.workers.try_acquire().ok())
+                .take(max)
+                .collect()
+        };
+        BorrowedWorkers { permits }
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        QueueStatus {
+            workers: self.worker_count,
+            running: self.worker_count - self.workers.available_permits(),
+            waiting: self.waiting.load(Ordering::SeqCst),
+            max_depth: self.max_depth,
+            estimated_wait_secs: self.estimated_wait_secs(),
+        }
+    }
+
+    fn estimated_wait_secs(&self) -> u64 {
+        self.wait_secs(self.waiting.load(Ordering::SeqCst) as u64 + 1)
+    }
+
+    // How long until the request `ahead` places back in line gets a worker
+    fn wait_secs(&self, ahead: u64) -> u64 {
+        let avg_run_ms = self.avg_run_ms.load(Ordering::Relaxed);
+        (ahead * avg_run_ms)
+            .div_ceil(self.worker_count as u64 * 1000)
+            .max(1)
+    }
+
+    fn ticket<'a>(
+        &'a self,
+        permit: SemaphorePermit<'a>,
+        position: usize,
+        arrived: Instant,
+    ) -> Ticket<'a> {
+        Ticket {
+            _permit: permit,
+            queue: self,
+            position,
+            waited: arrived.elapsed(),
+            started: Instant::now(),
+        }
+    }
+
+    fn record_run(&self, took: Duration) {
+        // Exponential moving average; a lost update under contention only skews the estimate
+        let avg = self.avg_run_ms.load(Ordering::Relaxed);
+        let sample = took.as_millis() as u64;
+        self.avg_run_ms
+            .store((avg * 7 + sample) / 8, Ordering::Relaxed);
+    }
+}
+
+impl<'a> Place<'a> {
+    /// 0 when a worker was free, otherwise 1 for the next in line.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn estimated_wait_secs(&self) -> u64 {
+        if self.position == 0 {
+            0
+        } else {
+            self.queue.wait_secs(self.position as u64)
+        }
+    }
+
+    /// Waits for a worker; the place in line is given up once one is free.
+    pub async fn ready(self) -> Ticket<'a> {
+        let permit = match self.permit {
+            Some(permit) => permit,
+            None => self
+                .queue
+                .workers
+                .acquire()
+                .await
+                .expect("run queue semaphore is never closed"),
+        };
+        self.queue.ticket(permit, self.position, self.arrived)
+    }
+}
+
+impl Ticket<'_> {
+    pub fn info(&self) -> QueueInfo {
+        QueueInfo {
+            position: self.position,
+            waited_ms: self.waited.as_millis() as u64,
+        }
+    }
+}
+
+impl BorrowedWorkers<'_> {
+    pub fn len(&self) -> usize {
+        self.permits.len()
+    }
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        self.queue.record_run(self.started.elapsed());
+    }
+}
+
+// Leaves the line even if the request is dropped while waiting (e.g. the client disconnected)
+struct InLine<'a>(&'a AtomicUsize);
+
+impl Drop for InLine<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RunQueue;
+
+    #[tokio::test]
+    async fn places_in_line_are_known_before_waiting() {
+        let queue = RunQueue::new(1, 2);
+        let first = queue.join().unwrap();
+        assert_eq!((first.position(), first.estimated_wait_secs()), (0, 0));
+        let running = first.ready().await;
+
+        let second = queue.join().unwrap();
+        let third = queue.join().unwrap();
+        assert_eq!((second.position(), third.position()), (1, 2));
+        assert!(third.estimated_wait_secs() >= second.estimated_wait_secs());
+        assert!(second.estimated_wait_secs() > 0);
+        assert!(queue.join().is_err());
+        assert_eq!(queue.status().waiting, 2);
+
+        drop(third);
+        drop(running);
+        let ticket = second.ready().await;
+        assert_eq!(ticket.info().position, 1);
+        assert_eq!(queue.status().waiting, 0);
+    }
+}
@@ -0,0 +1,337 @@
+use std::{
+    borrow::Cow,
+    io,
+    os::unix::process::ExitStatusExt,
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::{Child, Command},
+    time,
+};
+
+use crate::{compilers::Compiler, run_config::RunConfig};
+
+mod runner;
+
+/// Environment variable that switches a re-exec of the server binary into runner mode.
+pub(crate) const RUNNER_ENV: &str = "ZEN_SANDBOX_RUNNER";
+
+// Room for the JSON envelope around the program output in a runner reply
+const REPLY_OVERHEAD_BYTES: usize = 64 * 1024;
+
+// Borrowed on the server side so a quiz does not copy the source for every testcase
+#[derive(Serialize, Deserialize)]
+pub(crate) struct RunRequest<'a> {
+    pub(crate) code: Cow<'a, str>,
+    pub(crate) input: Cow<'a, str>,
+    pub(crate) max_output_bytes: usize,
+    /// Runs the interpreter in debug mode and returns what it printed as the trace.
+    pub(crate) debug: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum RunReply {
+    Output {
+        output: String,
+        dropped_bytes: usize,
+    },
+    Error {
+        msg: String,
+        error_type: String,
+    },
+    Panic(String),
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct RunnerReply {
+    pub(crate) outcome: RunReply,
+    pub(crate) metrics: Metrics,
+    pub(crate) trace: Option<String>,
+}
+
+/// Resource usage of one run. CPU time and peak memory are reported by the runner itself, so
+/// they are missing when it was killed or crashed first.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Metrics {
+    pub wall_time_ms: u64,
+    pub cpu_time_ms: Option<u64>,
+    pub peak_memory_kb: Option<u64>,
+}
+
+/// How a sandboxed run ended, as seen from the server.
+#[derive(Debug)]
+pub enum RunResult {
+    /// The program finished; output beyond the configured limit was cut off and counted.
+    Output {
+        output: String,
+        dropped_bytes: usize,
+    },
+    /// The zen interpreter rejected the program and reported an error.
+    Error {
+        msg: String,
+        error_type: String,
+    },
+    /// The runner died or misbehaved: panic, signal, resource limit or garbled reply.
+    RuntimeError(String),
+    TimeLimitExceeded,
+}
+
+#[derive(Debug)]
+pub struct Run {
+    pub result: RunResult,
+    pub metrics: Metrics,
+    /// What the interpreter printed in debug mode, cut to the output limit.
+    pub trace: Option<String>,
+}
+
+impl From<RunReply> for RunResult {
+    fn from(reply: RunReply) -> Self {
+        match reply {
+            RunReply::Output {
+                output,
+                dropped_bytes,
+            } => RunResult::Output {
+                output,
+                dropped_bytes,
+            },
+            RunReply::Error { msg, error_type } => RunResult::Error { msg, error_type },
+            RunReply::Panic(msg) => {
+                RunResult::RuntimeError(format!("Interpreter crashed\n{}", msg))
+            }
+        }
+    }
+}
+
+/// Runs `code` on `compiler` in a fresh child process with the CPU, memory and output limits
+/// from `config`.
+///
+/// The child is killed once `config.time_limit` of wall-clock time has passed.
+pub async fn run(
+    compiler: &Compiler,
+    code: &str,
+    input: &str,
+    debug: bool,
+    config: &RunConfig,
+) -> Run {
+    let started = Instant::now();
+    let (result, reported, trace) = run_in_child(compiler, code, input, debug, config).await;
+    let metrics = reported.unwrap_or(Metrics {
+        wall_time_ms: started.elapsed().as_millis() as u64,
+        cpu_time_ms: None,
+        peak_memory_kb: None,
+    });
+    Run {
+        result,
+        metrics,
+        trace,
+    }
+}
+
+// Metrics and the trace are only known when the runner replied
+async fn run_in_child(
+    compiler: &Compiler,
+    code: &str,
+    input: &str,
+    debug: bool,
+    config: &RunConfig,
+) -> (RunResult, Option<Metrics>, Option<String>) {
+    let mut child = match spawn_runner(compiler, config) {
+        Ok(child) => child,
+        Err(e) => {
+            let msg = format!("Could not start sandbox: {}", e);
+            return (RunResult::RuntimeError(msg), None, None);
+        }
+    };
+
+    let request = serde_json::to_vec(&RunRequest {
+        code: Cow::Borrowed(code),
+        input: Cow::Borrowed(input),
+        max_output_bytes: config.max_output_bytes,
+        debug,
+    })
+    .unwrap();
+    let reply_limit = reply_limit(config);
+
+    let exchanged = time::timeout(
+        config.time_limit,
+        exchange(&mut child, request, reply_limit),
+    )
+    .await;
+
+    match exchanged {
+        Ok(Ok((reply, status, cpu_time))) => {
+            if reply.len() > reply_limit {
+                let msg = format!(
+                    "Output limit exceeded\nProgram printed more than {} bytes",
+                    config.max_output_bytes
+                );
+                return (RunResult::RuntimeError(msg), None, None);
+            }
+            match status.signal() {
+                Some(libc::SIGXCPU) => (RunResult::TimeLimitExceeded, None, None),
+                // The hard CPU limit kills a program that ignored SIGXCPU; any other SIGKILL comes
+                // from the OOM killer
+                Some(libc::SIGKILL)
+                    if cpu_time.is_some_and(|cpu| cpu.as_secs() >= config.cpu_limit_secs) =>
+                {
+                    (RunResult::TimeLimitExceeded, None, None)
+                }
+                Some(signal) => (RunResult::RuntimeError(describe_signal(signal)), None, None),
+                None => match serde_json::from_slice::<RunnerReply>(&reply) {
+                    Ok(RunnerReply {
+                        outcome,
+                        metrics,
+                        trace,
+                    }) => (outcome.into(), Some(metrics), trace),
+                    Err(_) => {
+                        let msg = format!("Interpreter crashed\nRunner exited with {}", status);
+                        (RunResult::RuntimeError(msg), None, None)
+                    }
+                },
+            }
+        }
+        Ok(Err(e)) => {
+            let _ = child.kill().await;
+            let msg = format!("Sandbox I/O failed: {}", e);
+            (RunResult::RuntimeError(msg), None, None)
+        }
+        Err(_) => {
+            let _ = child.kill().await;
+            (RunResult::TimeLimitExceeded, None, None)
+        }
+    }
+}
+
+fn spawn_runner(compiler: &Compiler, config: &RunConfig) -> io::Result<Child> {
+    let mut command = match &compiler.runner {
+        Some(runner) => Command::new(runner),
+        None => Command::new(std::env::current_exe()?),
+    };
+    command
+        .env_clear()
+        .env(RUNNER_ENV, "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+
+    let cpu_limit = config.cpu_limit_secs;
+    let memory_limit = config.memory_limit_bytes;
+    // SAFETY: the closure only calls setrlimit, which is async-signal-safe.
+    unsafe {
+        command.pre_exec(move || {
+            // The hard CPU limit is one second above the soft one so SIGXCPU arrives first
+            set_limit(libc::RLIMIT_CPU, cpu_limit, cpu_limit + 1)?;
+            set_limit(libc::RLIMIT_AS, memory_limit, memory_limit)?;
+            // Programs have no reason to write files. This does not limit output, which goes
+            // through pipes; `exchange` caps the reply instead
+            set_limit(libc::RLIMIT_FSIZE, 0, 0)?;
+            set_limit(libc::RLIMIT_CORE, 0, 0)
+        });
+    }
+
+    command.spawn()
+}
+
+// Also returns the CPU time the runner used, where the platform reports it for killed processes
+async fn exchange(
+    child: &mut Child,
+    request: Vec<u8>,
+    reply_limit: usize,
+) -> io::Result<(Vec<u8>, std::process::ExitStatus, Option<Duration>)> {
+    let mut stdin = child.stdin.take().expect("runner stdin is piped");
+    stdin.write_all(&request).await?;
+    drop(stdin);
+
+    let mut reply = Vec::new();
+    let stdout = child.stdout.take().expect("runner stdout is piped");
+    stdout
+        .take(reply_limit as u64 + 1)
+        .read_to_end(&mut reply)
+        .await?;
+    if reply.len() > reply_limit {
+        child.kill().await?;
+    }
+
+    let cpu_time = match child.id() {
+        Some(pid) => tokio::task::spawn_blocking(move || cpu_time_at_exit(pid))
+            .await
+            .unwrap_or(None),
+        None => None,
+    };
+    let status = child.wait().await?;
+    Ok((reply, status, cpu_time))
+}
+
+// Waits for the child to exit without reaping it, so `Child::wait` still collects its status.
+// Only the raw waitid syscall reports resource usage; the libc wrapper has no argument for it
+#[cfg(target_os = "linux")]
+fn cpu_time_at_exit(pid: u32) -> Option<Duration> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let waited = unsafe {
+        libc::syscall(
+            libc::SYS_waitid,
+            libc::P_PID,
+            pid,
+            &mut info,
+            libc::WEXITED | libc::WNOWAIT,
+            &mut usage,
+        )
+    };
+    if waited != 0 {
+        return None;
+    }
+    let seconds = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec.max(0) as u64)
+            + Duration::from_micros(time.tv_usec.max(0) as u64)
+    };
+    Some(seconds(usage.ru_utime) + seconds(usage.ru_stime))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cpu_time_at_exit(_pid: u32) -> Option<Duration> {
+    None
+}
+
+// The runner truncates output and trace to the configured limit each, but JSON may escape every
+// byte into six (`\u00XX`), so the reply itself can legitimately be larger than that
+fn reply_limit(config: &RunConfig) -> usize {
+    config.max_output_bytes * 12 + REPLY_OVERHEAD_BYTES
+}
+
+// glibc declares its own type for resources, everything else (musl, macOS) a plain int
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+fn set_limit(resource: Resource, soft: u64, hard: u64) -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: soft,
+        rlim_max: hard,
+    };
+    if unsafe { libc::setrlimit(resource, &limit) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+fn describe_signal(signal: i32) -> String {
+    let reason = match signal {
+        libc::SIGSEGV => "stack overflow or invalid memory access",
+        libc::SIGABRT => "out of memory or aborted",
+        libc::SIGXFSZ => "tried to write a file",
+        libc::SIGKILL => "likely out of memory",
+        _ => "killed",
+    };
+    format!(
+        "Program crashed\nRunner received signal {} ({})",
+        signal, reason
+    )
+}
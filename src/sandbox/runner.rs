@@ -0,0 +1,201 @@
+use std::{
+    any::Any,
+    fs::File,
+    io::{self, Read, Write},
+    os::fd::{FromRawFd, OwnedFd, RawFd},
+    panic::{self, AssertUnwindSafe},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use zen::run_program;
+
+use super::{Metrics, RunReply, RunRequest, RunnerReply, RUNNER_ENV};
+
+// `#[shuttle_runtime::main]` owns `main`, so runner mode is entered from a constructor that runs
+// before it. In the server process the variable is unset and the hook returns immediately.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+compile_error!("the sandbox runner needs an ELF or Mach-O constructor section");
+
+#[used]
+#[cfg_attr(target_os = "linux", unsafe(link_section = ".init_array"))]
+#[cfg_attr(target_os = "macos", unsafe(link_section = "__DATA,__mod_init_func"))]
+static RUNNER_HOOK: extern "C" fn() = runner_hook;
+
+extern "C" fn runner_hook() {
+    if std::env::var_os(RUNNER_ENV).is_some() {
+        std::process::exit(serve());
+    }
+}
+
+/// Reads one [`RunRequest`] from stdin, runs it and writes the [`RunnerReply`] to stdout.
+fn serve() -> i32 {
+    let mut request = String::new();
+    if io::stdin().read_to_string(&mut request).is_err() {
+        return 2;
+    }
+    let RunRequest {
+        code,
+        input,
+        max_output_bytes,
+        debug,
+    } = match serde_json::from_str(&request) {
+        Ok(request) => request,
+        Err(_) => return 2,
+    };
+
+    // Panics are reported in the reply, so keep the default hook from printing them
+    panic::set_hook(Box::new(|_| {}));
+    let capture = match Capture::start(max_output_bytes) {
+        Ok(capture) => capture,
+        Err(_) => return 2,
+    };
+    let started = Instant::now();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        run_program(code.into_owned(), &input, debug)
+    }));
+    let wall_time = started.elapsed();
+    let mut trace = match capture.finish() {
+        Ok(trace) => trace,
+        Err(_) => return 2,
+    };
+    let outcome = match result {
+        Ok(Ok(mut output)) => {
+            let dropped_bytes = truncate(&mut output, max_output_bytes);
+            RunReply::Output {
+                output,
+                dropped_bytes,
+            }
+        }
+        Ok(Err(err)) => RunReply::Error {
+            msg: err.msg.to_string(),
+            error_type: err.error_type.to_string(),
+        },
+        Err(payload) => RunReply::Panic(panic_message(payload)),
+    };
+    let reply = RunnerReply {
+        outcome,
+        metrics: measure(wall_time),
+        trace: debug.then(|| {
+            truncate(&mut trace, max_output_bytes);
+            trace
+        }),
+    };
+
+    let mut stdout = io::stdout().lock();
+    if serde_json::to_writer(&mut stdout, &reply).is_err() || stdout.flush().is_err() {
+        return 2;
+    }
+    0
+}
+
+/// Cuts `output` down to at most `max_bytes` on a char boundary and returns how many bytes went.
+fn truncate(output: &mut String, max_bytes: usize) -> usize {
+    if output.len() <= max_bytes {
+        return 0;
+    }
+    let mut end = max_bytes;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    let dropped_bytes = output.len() - end;
+    output.truncate(end);
+    dropped_bytes
+}
+
+/// Points stdout and stderr at a pipe while the interpreter runs, so nothing it prints (such as
+/// its debug trace) ends up in the reply that is written to stdout afterwards.
+///
+/// A thread drains the pipe and keeps only the first `max_bytes`, so a long trace costs neither
+/// memory nor a write that fails halfway through the run.
+struct Capture {
+    writer: OwnedFd,
+    reader: JoinHandle<io::Result<Vec<u8>>>,
+    saved_stdout: RawFd,
+    saved_stderr: RawFd,
+}
+
+impl Capture {
+    fn start(max_bytes: usize) -> io::Result<Self> {
+        let mut fds = [0; 2];
+        check(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+        // SAFETY: pipe returned two new descriptors that nothing else owns.
+        let (read_end, writer) =
+            unsafe { (File::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        let reader = thread::spawn(move || {
+            let mut read_end = read_end;
+            let mut kept = Vec::new();
+            (&mut read_end)
+                .take(max_bytes as u64)
+                .read_to_end(&mut kept)?;
+            // The rest is read and dropped, so the interpreter never blocks on a full pipe
+            io::copy(&mut read_end, &mut io::sink())?;
+            Ok(kept)
+        });
+        let capture = Capture {
+            saved_stdout: check(unsafe { libc::dup(libc::STDOUT_FILENO) })?,
+            saved_stderr: check(unsafe { libc::dup(libc::STDERR_FILENO) })?,
+            writer,
+            reader,
+        };
+        check(unsafe { libc::dup2(fds[1], libc::STDOUT_FILENO) })?;
+        check(unsafe { libc::dup2(fds[1], libc::STDERR_FILENO) })?;
+        Ok(capture)
+    }
+
+    /// Restores stdout and stderr and returns the start of everything printed in between.
+    fn finish(self) -> io::Result<String> {
+        io::stdout().flush()?;
+        check(unsafe { libc::dup2(self.saved_stdout, libc::STDOUT_FILENO) })?;
+        check(unsafe { libc::dup2(self.saved_stderr, libc::STDERR_FILENO) })?;
+        // With no descriptor left writing to the pipe, the reader sees its end
+        drop(self.writer);
+        let kept = self
+            .reader
+            .join()
+            .map_err(|_| io::Error::other("trace reader panicked"))??;
+        Ok(String::from_utf8_lossy(&kept).into_owned())
+    }
+}
+
+fn check(result: libc::c_int) -> io::Result<RawFd> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+fn measure(wall_time: Duration) -> Metrics {
+    // SAFETY: getrusage only writes into the zeroed struct we hand it.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let measured = unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } == 0;
+
+    let millis = |time: libc::timeval| time.tv_sec as u64 * 1000 + time.tv_usec as u64 / 1000;
+    Metrics {
+        wall_time_ms: wall_time.as_millis() as u64,
+        cpu_time_ms: measured.then(|| millis(usage.ru_utime) + millis(usage.ru_stime)),
+        peak_memory_kb: measured.then_some(max_rss_kb(usage.ru_maxrss as u64)),
+    }
+}
+
+// ru_maxrss is in kilobytes on Linux but in bytes on macOS
+#[cfg(target_os = "macos")]
+fn max_rss_kb(max_rss: u64) -> u64 {
+    max_rss / 1024
+}
+
+#[cfg(not(target_os = "macos"))]
+fn max_rss_kb(max_rss: u64) -> u64 {
+    max_rss
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}